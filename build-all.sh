@@ -2,7 +2,7 @@
 
 set -euo pipefail
 
-DIRS="os testsuite/stm32f4 testsuite/stm32g0 examples/*/*"
+DIRS="os testsuite/stm32f4 testsuite/stm32g0 testsuite/hosted testsuite/hosted-10khz examples/*/*"
 
 for d in $DIRS; do
     echo "---- building in $d"
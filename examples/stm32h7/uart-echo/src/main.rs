@@ -202,16 +202,17 @@ fn usart_echo<'usart>(
         let (q_push, q_pop) = q.split();
 
         // "Fork" into the rx and tx processes. This is a very convenient way to
-        // manage the two sides of the link, but has the caveat that this task
-        // will be woken and _both_ will be polled on either tx or rx interrupts
-        // (because Notify designates a task, not a future within it). This may
-        // not matter for your application.
+        // manage the two sides of the link. We use the `join` from
+        // `lilos::branch` rather than the one from `futures`, which gives each
+        // side its own waker, so that an rx interrupt only polls the rx side
+        // and vice versa. (This relies on the `foreign-wakers` feature, so that
+        // the `Notify`s used by the ISR can hold on to each side's waker.)
         //
         // The trailing ".0" here is because we're joining two nonterminating
         // futures, giving type (!, !), which Rust doesn't think is uninhabited --
         // by extracting either one of the !s we prove that code past this point is
         // unreachable.
-        futures::future::join(echo_rx(usart, q_push), echo_tx(usart, q_pop)).await.0
+        lilos::branch::join(echo_rx(usart, q_push), echo_tx(usart, q_pop)).await.0
     }
 }
 
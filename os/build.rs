@@ -1,13 +1,49 @@
 fn main() {
-    match std::env::var("TARGET").unwrap().as_str() {
+    let target = std::env::var("TARGET").unwrap();
+
+    if std::env::var_os("CARGO_FEATURE_HOSTED").is_some() {
+        // The hosted backend runs on top of std on the build machine, which
+        // always has native atomics and no BASEPRI to speak of.
+        if target.starts_with("thumb") || target.starts_with("riscv") {
+            panic!("the hosted feature needs a std target, not {}", target);
+        }
+        println!("cargo:rustc-cfg=feature=\"has-native-rmw\"");
+        return;
+    }
+
+    match target.as_str() {
         "thumbv7m-none-eabi" | "thumbv7em-none-eabihf" => {
             // Turn on BASEPRI support for interrupt priority filtering.
             println!("cargo:rustc-cfg=feature=\"has-basepri\"");
             // Use native atomic RMW operations
             println!("cargo:rustc-cfg=feature=\"has-native-rmw\"");
+            // DWT cycle counter is available for timing.
+            println!("cargo:rustc-cfg=feature=\"has-cyccnt\"");
+            // SysTick is the built-in timer.
+            println!("cargo:rustc-cfg=feature=\"has-systick\"");
+            // The MPU, where fitted, is the ARMv7-M kind.
+            println!("cargo:rustc-cfg=feature=\"has-pmsav7\"");
         }
-        "thumbv6m-none-eabi" => {
-            // Don't turn anything on.
+        "thumbv8m.main-none-eabi" | "thumbv8m.main-none-eabihf" => {
+            // ARMv8-M mainline (Cortex-M33, M55) keeps everything ARMv7-M has
+            // that we care about.
+            println!("cargo:rustc-cfg=feature=\"has-basepri\"");
+            println!("cargo:rustc-cfg=feature=\"has-native-rmw\"");
+            println!("cargo:rustc-cfg=feature=\"has-cyccnt\"");
+            println!("cargo:rustc-cfg=feature=\"has-systick\"");
+        }
+        "thumbv6m-none-eabi" | "thumbv8m.base-none-eabi" => {
+            // SysTick is the built-in timer, and that's about it. ARMv8-M
+            // baseline (Cortex-M23) has no BASEPRI or cycle counter either. It
+            // does have exclusive loads and stores, but we use the same
+            // polyfills as ARMv6-M there rather than maintain a third path.
+            println!("cargo:rustc-cfg=feature=\"has-systick\"");
+        }
+        "riscv32imac-unknown-none-elf" => {
+            // The A extension gives us native atomic RMW operations.
+            println!("cargo:rustc-cfg=feature=\"has-native-rmw\"");
+            // The machine timer in the CLINT is the built-in timer.
+            println!("cargo:rustc-cfg=feature=\"has-mtimer\"");
         }
         t => {
             panic!("unknown target {}, update build.rs", t);
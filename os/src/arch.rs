@@ -0,0 +1,31 @@
+//! Processor support.
+//!
+//! Everything the executor needs from the underlying machine -- masking
+//! interrupts, sleeping until something happens, figuring out whether we're in
+//! an interrupt handler -- goes through this module, so that the rest of the OS
+//! doesn't have to care what it's running on.
+//!
+//! There are currently three implementations:
+//!
+//! - `arm` for ARM M-profile microcontrollers, the normal case.
+//! - `riscv` for single-hart RISC-V microcontrollers running in machine mode.
+//! - `hosted` for running on top of `std` on a development machine, when the
+//!   `hosted` feature is enabled. Here, "interrupts" are other threads that
+//!   poke the executor through `Notify` and friends.
+//!
+//! Each provides the same set of `pub(crate)` functions. The hardware timer
+//! behind the `time` module is handled over there, since it's tangled up with
+//! the tick counter; see `time::initialize_sys_tick` and `time::mtimer`.
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "hosted")] {
+        mod hosted;
+        pub(crate) use hosted::*;
+    } else if #[cfg(target_arch = "riscv32")] {
+        mod riscv;
+        pub(crate) use self::riscv::*;
+    } else {
+        mod arm;
+        pub(crate) use arm::*;
+    }
+}
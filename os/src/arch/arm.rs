@@ -0,0 +1,94 @@
+//! ARM M-profile implementation of the processor support routines.
+
+/// Runs `body` with interrupts disabled using PRIMASK, restoring the previous
+/// PRIMASK state afterwards.
+#[inline(always)]
+pub(crate) fn with_interrupts_masked<R>(body: impl FnOnce() -> R) -> R {
+    let prev = cortex_m::register::primask::read();
+    cortex_m::interrupt::disable();
+
+    let r = body();
+
+    if prev == cortex_m::register::primask::Primask::Active {
+        // Safety: interrupts were just on, so this won't compromise memory
+        // safety.
+        unsafe {
+            cortex_m::interrupt::enable();
+        }
+    }
+
+    r
+}
+
+/// Runs `body` in a critical section, where it can't be interrupted. This is
+/// for short operations on data shared with ISRs; unlike
+/// `with_interrupts_masked`, it's not a point where the executor expects
+/// anything to change.
+#[inline(always)]
+// Unused if we have native atomics and no feature needs a critical section.
+#[allow(dead_code)]
+pub(crate) fn critical_section<R>(body: impl FnOnce() -> R) -> R {
+    cortex_m::interrupt::free(|_| body())
+}
+
+/// Runs `body` with interrupts at `priority` and below masked using BASEPRI,
+/// restoring the previous BASEPRI value afterwards.
+#[cfg(feature = "has-basepri")]
+#[inline(always)]
+pub(crate) fn with_interrupts_filtered<R>(
+    priority: u8,
+    body: impl FnOnce() -> R,
+) -> R {
+    let prev = cortex_m::register::basepri::read();
+    cortex_m::register::basepri_max::write(priority);
+
+    let r = body();
+
+    // Safety: just restoring state
+    unsafe {
+        cortex_m::register::basepri::write(prev);
+    }
+
+    r
+}
+
+/// Ensures that changes to interrupt masking take effect before the next
+/// instruction, so that newly-enabled interrupt handlers get a chance to fire.
+#[inline(always)]
+pub(crate) fn isb() {
+    cortex_m::asm::isb();
+}
+
+/// Idles the processor until an interrupt arrives.
+#[inline(always)]
+pub(crate) fn wait_for_interrupt() {
+    cortex_m::asm::wfi();
+}
+
+/// Called by the executor as it starts up. Nothing to do here.
+#[inline(always)]
+pub(crate) fn executor_started() {}
+
+/// Called whenever task wake bits are set. The hardware will wake us from
+/// `wait_for_interrupt` on its own, so there's nothing to do here.
+#[inline(always)]
+pub(crate) fn wake_executor() {}
+
+/// Checks whether we're running in an interrupt service routine (ISR).
+#[inline(always)]
+// Unused if none of the features that check for ISRs are enabled.
+#[allow(dead_code)]
+pub(crate) fn in_isr() -> bool {
+    interrupt_context() != 0
+}
+
+/// Identifies the ISR we're running in, if any: this is the number of the
+/// exception being handled, or 0 in Thread mode.
+#[inline(always)]
+// Unused if none of the features that check for ISRs are enabled.
+#[allow(dead_code)]
+pub(crate) fn interrupt_context() -> usize {
+    let psr_value = cortex_m::register::apsr::read().bits();
+    // Bottom 9 bits are the exception number, which are 0 in Thread mode.
+    (psr_value & 0x1FF) as usize
+}
@@ -0,0 +1,156 @@
+//! Hosted implementation of the processor support routines, for running on
+//! top of `std`.
+//!
+//! The executor runs on whichever thread calls `run_tasks`. Other threads play
+//! the role of interrupt service routines: they can do anything an ISR can
+//! (which mostly means calling `Notify::notify`) and nothing an ISR can't.
+//!
+//! There are no interrupts to mask, so the masking routine mostly just runs its
+//! body. Idling parks the executor thread until some other thread wakes a
+//! task, or until a millisecond has passed -- the same rate at which SysTick
+//! would wake the processor on real hardware, which is what makes timers work.
+
+use std::sync::OnceLock;
+use std::thread::{self, Thread};
+#[cfg(not(feature = "tickless"))]
+use std::time::Duration;
+
+/// The thread running the executor, once it's started.
+static EXECUTOR_THREAD: OnceLock<Thread> = OnceLock::new();
+
+/// Runs `body`. With no interrupts to mask, there's not much to do, except
+/// that this is the last moment before "interrupts are masked" -- so it's where
+/// we give the simulated SysTick its chance to advance the clock.
+#[inline(always)]
+pub(crate) fn with_interrupts_masked<R>(body: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "time")]
+    crate::time::catch_up_host_clock();
+
+    body()
+}
+
+/// Runs `body` in a critical section, excluding any other thread that's doing
+/// the same. This is for short operations on data shared with ISRs; unlike
+/// `with_interrupts_masked`, it's not a point where the executor expects
+/// anything to change.
+///
+/// Critical sections nest, as they do on hardware: a thread that's already in
+/// one just runs `body`.
+#[cfg(any(
+    feature = "foreign-wakers",
+    feature = "time",
+    feature = "stats",
+    feature = "task-isolation",
+))]
+pub(crate) fn critical_section<R>(body: impl FnOnce() -> R) -> R {
+    use core::cell::Cell;
+
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    std::thread_local! {
+        /// Number of critical sections the current thread is in. While this
+        /// is nonzero, the thread holds `LOCK`.
+        static DEPTH: Cell<usize> = Cell::new(0);
+    }
+
+    /// Leaves one level of critical section when dropped, even if `body`
+    /// panics.
+    struct Leave;
+
+    impl Drop for Leave {
+        fn drop(&mut self) {
+            DEPTH.with(|d| d.set(d.get() - 1));
+        }
+    }
+
+    // Poisoning just means some other critical section panicked; the lock
+    // guards no data of its own, so that doesn't matter to us.
+    let _guard = if DEPTH.with(Cell::get) == 0 {
+        Some(LOCK.lock().unwrap_or_else(|e| e.into_inner()))
+    } else {
+        None
+    };
+    DEPTH.with(|d| d.set(d.get() + 1));
+    // Declared after `_guard`, so it's dropped before the lock is released.
+    let _leave = Leave;
+    body()
+}
+
+/// Stands in for the instruction barrier used on real hardware. A compiler
+/// fence is enough to keep the executor loop from being reordered around it.
+#[inline(always)]
+pub(crate) fn isb() {
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+/// Parks the executor thread until a task is woken, or one tick passes. With
+/// the `tickless` feature, this waits until the next timer deadline instead,
+/// if the executor has said it's idling.
+///
+/// If the simulated SysTick is behind, this returns right away instead, the
+/// same way WFI does when an interrupt is already pending.
+pub(crate) fn wait_for_interrupt() {
+    #[cfg(feature = "time")]
+    if crate::time::host_clock_behind() {
+        return;
+    }
+
+    #[cfg(feature = "tickless")]
+    match crate::time::host_park_timeout() {
+        Some(timeout) => thread::park_timeout(timeout),
+        None => thread::park(),
+    }
+    #[cfg(not(feature = "tickless"))]
+    thread::park_timeout(Duration::from_millis(1));
+}
+
+/// Records the calling thread as the executor thread.
+///
+/// # Panics
+///
+/// If an executor has already been started on a different thread.
+pub(crate) fn executor_started() {
+    let current = thread::current();
+    let executor = EXECUTOR_THREAD.get_or_init(|| current.clone());
+    if executor.id() != current.id() {
+        panic!("lilos executor already running on another thread");
+    }
+}
+
+/// Unparks the executor thread, if it's started, so that it notices newly set
+/// wake bits.
+#[inline(always)]
+pub(crate) fn wake_executor() {
+    if let Some(t) = EXECUTOR_THREAD.get() {
+        t.unpark();
+    }
+}
+
+/// Checks whether we're running in the hosted equivalent of an ISR, which is to
+/// say, any thread other than the executor.
+// Unused if none of the features that check for ISRs are enabled.
+#[allow(dead_code)]
+pub(crate) fn in_isr() -> bool {
+    match EXECUTOR_THREAD.get() {
+        Some(t) => t.id() != thread::current().id(),
+        None => false,
+    }
+}
+
+/// Identifies the hosted equivalent of the ISR we're running in: 0 on the
+/// executor thread (or anywhere, before the executor starts), and a number
+/// unique to the calling thread otherwise.
+#[cfg(feature = "interrupt-executor")]
+pub(crate) fn interrupt_context() -> usize {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+    std::thread_local! {
+        static ID: usize = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if in_isr() {
+        ID.with(|id| *id)
+    } else {
+        0
+    }
+}
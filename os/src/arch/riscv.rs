@@ -0,0 +1,86 @@
+//! RISC-V implementation of the processor support routines, for single-hart
+//! parts running in machine mode, like the `riscv32imac` microcontrollers (and
+//! QEMU's `virt` machine).
+//!
+//! Interrupts are masked using the global `mstatus.MIE` bit. There's no
+//! equivalent of BASEPRI in the base architecture, so the `Filtered` interrupt
+//! policy isn't available.
+//!
+//! Unlike M-profile ARM, RISC-V has no register that tells us whether we're in
+//! an interrupt handler. Instead, handlers installed by the OS (currently just
+//! the machine timer handler) count themselves using `isr_scope`, and
+//! `in_isr` checks the count. This means the OS can't tell when it's being
+//! called from _your_ interrupt handlers, so the checks that catch things like
+//! sleeping in an ISR won't fire there. Be careful.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "interrupt-executor")]
+compile_error!("the interrupt-executor feature isn't supported on RISC-V yet");
+
+/// Number of OS interrupt handlers currently running.
+static ISR_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs `body` with interrupts disabled using `mstatus.MIE`, restoring the
+/// previous state afterwards.
+#[inline(always)]
+pub(crate) fn with_interrupts_masked<R>(body: impl FnOnce() -> R) -> R {
+    riscv::interrupt::free(body)
+}
+
+/// Runs `body` in a critical section, where it can't be interrupted. This is
+/// for short operations on data shared with ISRs; unlike
+/// `with_interrupts_masked`, it's not a point where the executor expects
+/// anything to change.
+#[inline(always)]
+// Unused if no feature needs a critical section.
+#[allow(dead_code)]
+pub(crate) fn critical_section<R>(body: impl FnOnce() -> R) -> R {
+    riscv::interrupt::free(body)
+}
+
+/// Ensures that changes to interrupt masking take effect before the next
+/// instruction. RISC-V takes pending interrupts as soon as `mstatus.MIE` is
+/// set, so this only needs to stop the compiler from moving code across it.
+#[inline(always)]
+pub(crate) fn isb() {
+    core::sync::atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// Idles the processor until an interrupt arrives.
+#[inline(always)]
+pub(crate) fn wait_for_interrupt() {
+    // Safety: WFI is just a hint, and has no effect on memory.
+    unsafe {
+        riscv::asm::wfi();
+    }
+}
+
+/// Called by the executor as it starts up. Nothing to do here.
+#[inline(always)]
+pub(crate) fn executor_started() {}
+
+/// Called whenever task wake bits are set. The hardware will wake us from
+/// `wait_for_interrupt` on its own, so there's nothing to do here.
+#[inline(always)]
+pub(crate) fn wake_executor() {}
+
+/// Checks whether we're running in one of the OS's interrupt handlers. See
+/// the module docs for why that's all we can check.
+#[inline(always)]
+// Unused if none of the features that check for ISRs are enabled.
+#[allow(dead_code)]
+pub(crate) fn in_isr() -> bool {
+    ISR_DEPTH.load(Ordering::Relaxed) != 0
+}
+
+/// Runs `body` as an interrupt handler, so that `in_isr` knows about it.
+#[inline(always)]
+// Unused if the OS isn't installing any interrupt handlers.
+#[allow(dead_code)]
+pub(crate) fn isr_scope<R>(body: impl FnOnce() -> R) -> R {
+    ISR_DEPTH.fetch_add(1, Ordering::Relaxed);
+    let r = body();
+    ISR_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    r
+}
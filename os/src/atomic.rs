@@ -4,7 +4,8 @@
 //! operations available on most other ARM processors. In particular, they have
 //! no atomic swap or read-modify-write instructions. This module provides
 //! traits that use the native atomics on M3 and later, and fallback
-//! implementations on M0.
+//! implementations on M0. (The ARMv8-M baseline profile, used by the Cortex-M23,
+//! gets the M0 implementations too.)
 //!
 //! The M0 implementations rely on disabling interrupts. This means that:
 //!
@@ -35,6 +36,8 @@ pub trait AtomicArithExt: AtomicExt {
     fn fetch_add_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value;
     /// Atomically OR `val` into our contents, returning the original value.
     fn fetch_or_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value;
+    /// Atomically AND `val` into our contents, returning the original value.
+    fn fetch_and_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value;
 }
 
 #[cfg(feature = "has-native-rmw")]
@@ -54,6 +57,9 @@ impl AtomicArithExt for AtomicU32 {
     fn fetch_or_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         self.fetch_or(val, ordering)
     }
+    fn fetch_and_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
+        self.fetch_and(val, ordering)
+    }
 }
 
 #[cfg(feature = "has-native-rmw")]
@@ -73,6 +79,9 @@ impl AtomicArithExt for AtomicUsize {
     fn fetch_or_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         self.fetch_or(val, ordering)
     }
+    fn fetch_and_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
+        self.fetch_and(val, ordering)
+    }
 }
 
 #[cfg(feature = "has-native-rmw")]
@@ -103,7 +112,7 @@ impl<T> AtomicExt for AtomicPtr<T> {
 
     fn swap_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         let (lo, so) = rmw_ordering(ordering);
-        cortex_m::interrupt::free(|_| {
+        crate::arch::critical_section(|| {
             let x = self.load(lo);
             self.store(val, so);
             x
@@ -118,7 +127,7 @@ impl AtomicExt for AtomicU32 {
     #[inline(always)]
     fn swap_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         let (lo, so) = rmw_ordering(ordering);
-        cortex_m::interrupt::free(|_| {
+        crate::arch::critical_section(|| {
             let x = self.load(lo);
             self.store(val, so);
             x
@@ -131,7 +140,7 @@ impl AtomicArithExt for AtomicU32 {
     #[inline(always)]
     fn fetch_add_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         let (lo, so) = rmw_ordering(ordering);
-        cortex_m::interrupt::free(|_| {
+        crate::arch::critical_section(|| {
             let x = self.load(lo);
             self.store(x.wrapping_add(val), so);
             x
@@ -141,12 +150,22 @@ impl AtomicArithExt for AtomicU32 {
     #[inline(always)]
     fn fetch_or_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         let (lo, so) = rmw_ordering(ordering);
-        cortex_m::interrupt::free(|_| {
+        crate::arch::critical_section(|| {
             let x = self.load(lo);
             self.store(x | val, so);
             x
         })
     }
+
+    #[inline(always)]
+    fn fetch_and_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
+        let (lo, so) = rmw_ordering(ordering);
+        crate::arch::critical_section(|| {
+            let x = self.load(lo);
+            self.store(x & val, so);
+            x
+        })
+    }
 }
 
 #[cfg(not(feature = "has-native-rmw"))]
@@ -156,7 +175,7 @@ impl AtomicExt for AtomicUsize {
     #[inline(always)]
     fn swap_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         let (lo, so) = rmw_ordering(ordering);
-        cortex_m::interrupt::free(|_| {
+        crate::arch::critical_section(|| {
             let x = self.load(lo);
             self.store(val, so);
             x
@@ -169,7 +188,7 @@ impl AtomicArithExt for AtomicUsize {
     #[inline(always)]
     fn fetch_add_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         let (lo, so) = rmw_ordering(ordering);
-        cortex_m::interrupt::free(|_| {
+        crate::arch::critical_section(|| {
             let x = self.load(lo);
             self.store(x.wrapping_add(val), so);
             x
@@ -179,12 +198,22 @@ impl AtomicArithExt for AtomicUsize {
     #[inline(always)]
     fn fetch_or_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         let (lo, so) = rmw_ordering(ordering);
-        cortex_m::interrupt::free(|_| {
+        crate::arch::critical_section(|| {
             let x = self.load(lo);
             self.store(x | val, so);
             x
         })
     }
+
+    #[inline(always)]
+    fn fetch_and_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
+        let (lo, so) = rmw_ordering(ordering);
+        crate::arch::critical_section(|| {
+            let x = self.load(lo);
+            self.store(x & val, so);
+            x
+        })
+    }
 }
 
 #[cfg(feature = "has-native-rmw")]
@@ -203,7 +232,7 @@ impl AtomicExt for AtomicBool {
     #[inline(always)]
     fn swap_polyfill(&self, val: Self::Value, ordering: Ordering) -> Self::Value {
         let (lo, so) = rmw_ordering(ordering);
-        cortex_m::interrupt::free(|_| {
+        crate::arch::critical_section(|| {
             let x = self.load(lo);
             self.store(val, so);
             x
@@ -0,0 +1,460 @@
+//! Concurrency combinators that give each branch its own `Waker`.
+//!
+//! When a task forks into concurrent branches using something like
+//! `futures::join!` or `select_biased!`, all the branches share the task's
+//! `Waker`. Any event that wakes one branch causes the task to be polled, and
+//! the combinator has no way of knowing which branch the event was for -- so it
+//! polls all of them. For a task with, say, a receive branch and a transmit
+//! branch, this means both get polled on every interrupt.
+//!
+//! The [`join`] and [`select`] combinators in this module (and their
+//! variations) instead hand each branch its own `Waker`, and only poll the
+//! branches whose `Waker`s have fired since the last poll. Wakeups still go to
+//! the task as a whole at the executor level; the extra precision comes from
+//! the combinator remembering which of its branches were the target.
+//!
+//! # Interaction with `Notify`
+//!
+//! A [`Notify`][crate::exec::Notify] usually tracks waiters by task, which
+//! would lose track of the branch. So, if a branch subscribes to a `Notify`,
+//! one of two things happens:
+//!
+//! - With the `foreign-wakers` feature, the `Notify` holds on to the branch's
+//!   `Waker` and wakes exactly that branch. (Each `Notify` has room for one
+//!   such `Waker`, which is plenty if, e.g., a receive branch and a transmit
+//!   branch each wait on their own interrupt's `Notify`.)
+//!
+//! - Without it, or if the `Notify` is already holding a different `Waker`,
+//!   the branch is flagged as needing to be polled whenever its task is, which
+//!   is the same behavior you'd get from `futures::join!`.
+//!
+//! Timers, and anything else built on [`list`][crate::list], always wake
+//! branches precisely.
+//!
+//! # Limits
+//!
+//! Tracking wakeups takes a slot in a small, fixed-size table shared by the
+//! whole program, which has room for 16 pending combinators at once. A
+//! combinator that can't get a slot (or that's being polled by something other
+//! than a `lilos` task, or a branch of another combinator in this module)
+//! still works, but falls back to polling all its branches every time.
+//!
+//! Wake tracking has room for one branch per bit of a `usize` (32 on the
+//! microcontrollers `lilos` supports). The combinators here only use up to
+//! four of them; for more branches, nest them.
+//!
+//! A [`TaskPool`][crate::pool::TaskPool] uses the same machinery to wake each
+//! of its futures precisely, and so takes up a slot too, while it has any
+//! futures running. That's what the rest of the branches are for: a pool can
+//! hold up to `usize::BITS` futures.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use pin_project_lite::pin_project;
+
+use crate::atomic::{AtomicArithExt, AtomicExt};
+use crate::exec;
+
+/// Number of slots in the table.
+const SLOT_COUNT: usize = 16;
+/// Bits used to encode a slot index in a waker.
+const SLOT_BITS: u32 = 4;
+/// Bits used to encode a branch index in a waker. This is enough for one
+/// branch per bit of a `usize`, which is how the branches are tracked.
+const BRANCH_BITS: u32 = usize::BITS.trailing_zeros();
+/// Maximum number of branches in one combinator.
+pub(crate) const MAX_BRANCHES: usize = 1 << BRANCH_BITS;
+/// Generation numbers take up the rest of the waker's data word.
+const GEN_SHIFT: u32 = SLOT_BITS + BRANCH_BITS;
+const GEN_MASK: usize = usize::MAX >> GEN_SHIFT;
+
+/// Wake tracking for one pending combinator.
+#[derive(Debug)]
+struct Slot {
+    /// Incremented each time the slot is released, so that wakers referring
+    /// to the previous owner stop working. Zero only until the slot is first
+    /// claimed (which keeps the table out of initialized data); after that
+    /// it's never zero, so that a branch waker's data word is never zero
+    /// either.
+    generation: AtomicUsize,
+    /// Index of the task that owns the combinator, if `parent` is zero.
+    task: AtomicUsize,
+    /// Data word of the branch waker the combinator was polled with, if it's
+    /// nested inside another combinator; zero otherwise.
+    parent: AtomicUsize,
+    /// Bitmask of branches whose wakers have fired.
+    fired: AtomicUsize,
+    /// Bitmask of branches that have subscribed to something that can only
+    /// wake their task as a whole, and so need polling whenever it's polled.
+    sticky: AtomicUsize,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            generation: AtomicUsize::new(0),
+            task: AtomicUsize::new(0),
+            parent: AtomicUsize::new(0),
+            fired: AtomicUsize::new(0),
+            sticky: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const SLOT: Slot = Slot::new();
+static SLOTS: [Slot; SLOT_COUNT] = [SLOT; SLOT_COUNT];
+
+/// Bitmask of slots that are in use. Slots are allocated and released by
+/// combinators being polled or dropped. That happens in task context, but
+/// tasks on an `InterruptExecutor` can preempt other tasks in the middle of
+/// it, so this is only ever changed with read-modify-write operations.
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies the combinator (by slot and generation) and branch that a waker
+/// belongs to.
+#[derive(Copy, Clone, Debug)]
+struct BranchId {
+    slot: usize,
+    generation: usize,
+    branch: usize,
+}
+
+impl BranchId {
+    fn pack(self) -> usize {
+        self.generation << GEN_SHIFT | self.branch << SLOT_BITS | self.slot
+    }
+
+    fn unpack(data: usize) -> Self {
+        Self {
+            slot: data & ((1 << SLOT_BITS) - 1),
+            branch: (data >> SLOT_BITS) & (MAX_BRANCHES - 1),
+            generation: data >> GEN_SHIFT,
+        }
+    }
+
+    /// Returns the slot, if it's still owned by the combinator this ID
+    /// refers to.
+    fn live_slot(self) -> Option<&'static Slot> {
+        let s = &SLOTS[self.slot];
+        if s.generation.load(Ordering::Acquire) == self.generation {
+            Some(s)
+        } else {
+            None
+        }
+    }
+
+    /// Marks this branch as fired and wakes whatever polls its combinator.
+    fn wake(self) {
+        // If the combinator is gone, there's nobody left to wake. If it's gone
+        // and its slot is reused between this check and the fetch_or, the new
+        // owner will see a spurious wakeup, which is harmless.
+        if let Some(s) = self.live_slot() {
+            s.fired.fetch_or_polyfill(1 << self.branch, Ordering::SeqCst);
+            match s.parent.load(Ordering::Relaxed) {
+                0 => exec::wake_task_by_index(s.task.load(Ordering::Relaxed)),
+                parent => BranchId::unpack(parent).wake(),
+            }
+        }
+    }
+
+    /// Flags this branch (and any enclosing branches) as sticky, and returns
+    /// the index of the task at the root.
+    fn make_sticky(self) -> Option<usize> {
+        let s = self.live_slot()?;
+        s.sticky.fetch_or_polyfill(1 << self.branch, Ordering::Relaxed);
+        match s.parent.load(Ordering::Relaxed) {
+            0 => Some(s.task.load(Ordering::Relaxed)),
+            parent => BranchId::unpack(parent).make_sticky(),
+        }
+    }
+}
+
+/// VTable for branch wakers. Like task wakers, they carry all their state in
+/// their data word, so cloning and dropping are free.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(
+    // clone
+    |p| RawWaker::new(p, &VTABLE),
+    // wake
+    |p| BranchId::unpack(p as usize).wake(),
+    // wake_by_ref
+    |p| BranchId::unpack(p as usize).wake(),
+    // drop
+    |_| (),
+);
+
+fn branch_waker(id: BranchId) -> Waker {
+    // Safety: our vtable functions treat the pointer as an integer and never
+    // dereference it, so they meet the Waker contract for any value.
+    unsafe {
+        Waker::from_raw(RawWaker::new(id.pack() as *const (), &VTABLE))
+    }
+}
+
+/// Returns the identity of `waker`, if it's a branch waker.
+fn branch_id_of(waker: &Waker) -> Option<BranchId> {
+    let id = BranchId::unpack(exec::extract_index(waker));
+    if waker.will_wake(&branch_waker(id)) {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Used by `Notify` when it can't hold on to a branch waker: flags the branch
+/// as needing to be polled whenever its task is, and returns the task's index
+/// so that the `Notify` can wake it. Returns `None` if `waker` isn't a branch
+/// waker, or belongs to a combinator that's gone.
+pub(crate) fn downgrade_to_task(waker: &Waker) -> Option<usize> {
+    branch_id_of(waker)?.make_sticky()
+}
+
+/// Wake tracking state embedded in each combinator.
+#[derive(Debug, Default)]
+pub(crate) struct Tracker {
+    /// Slot and generation, if we've got one.
+    slot: Option<(usize, usize)>,
+}
+
+impl Tracker {
+    /// Works out which branches need polling, claiming or updating our slot as
+    /// required. Returns a bitmask of branches.
+    pub(crate) fn begin_poll(&mut self, cx: &Context<'_>) -> usize {
+        let waker = cx.waker();
+        let (task, parent) = if let Some(task) = exec::task_index_of(waker) {
+            (task, 0)
+        } else if let Some(id) = branch_id_of(waker) {
+            (0, id.pack())
+        } else {
+            // Foreign waker; we can't tell anybody about our branches.
+            self.release();
+            return !0;
+        };
+
+        let (slot, fresh) = match self.slot {
+            Some((slot, _)) => (slot, false),
+            None => match claim_slot() {
+                Some(claim) => {
+                    self.slot = Some(claim);
+                    (claim.0, true)
+                }
+                None => return !0,
+            },
+        };
+
+        let s = &SLOTS[slot];
+        // Update ownership every time, in case we've moved.
+        s.task.store(task, Ordering::Relaxed);
+        s.parent.store(parent, Ordering::Relaxed);
+        let ready = s.fired.swap_polyfill(0, Ordering::SeqCst)
+            | s.sticky.swap_polyfill(0, Ordering::Relaxed);
+        if fresh {
+            // Anything could have happened before we were tracking it.
+            !0
+        } else {
+            ready
+        }
+    }
+
+    /// Produces the waker to use when polling `branch`.
+    pub(crate) fn waker(&self, branch: usize, cx: &Context<'_>) -> Waker {
+        match self.slot {
+            Some((slot, generation)) => branch_waker(BranchId {
+                slot,
+                generation,
+                branch,
+            }),
+            None => cx.waker().clone(),
+        }
+    }
+
+    pub(crate) fn release(&mut self) {
+        if let Some((slot, generation)) = self.slot.take() {
+            let mut next = generation.wrapping_add(1) & GEN_MASK;
+            if next == 0 {
+                next = 1;
+            }
+            SLOTS[slot].generation.store(next, Ordering::Release);
+            IN_USE.fetch_and_polyfill(!(1 << slot), Ordering::Release);
+        }
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Claims a free slot, returning its index and current generation.
+fn claim_slot() -> Option<(usize, usize)> {
+    let slot = loop {
+        let in_use = IN_USE.load(Ordering::Relaxed);
+        let slot = (!in_use).trailing_zeros() as usize;
+        if slot >= SLOT_COUNT {
+            return None;
+        }
+        // If something preempted us and took the slot first, try again.
+        let prev = IN_USE.fetch_or_polyfill(1 << slot, Ordering::Acquire);
+        if prev & 1 << slot == 0 {
+            break slot;
+        }
+    };
+    let s = &SLOTS[slot];
+    s.fired.store(0, Ordering::Relaxed);
+    s.sticky.store(0, Ordering::Relaxed);
+    let mut generation = s.generation.load(Ordering::Acquire);
+    if generation == 0 {
+        generation = 1;
+        s.generation.store(generation, Ordering::Release);
+    }
+    Some((slot, generation))
+}
+
+/// Polls `fut` if it's in the `ready` set and hasn't finished, storing its
+/// output if it finishes now.
+fn poll_branch<F: Future>(
+    tracker: &Tracker,
+    branch: usize,
+    ready: usize,
+    fut: Pin<&mut F>,
+    out: &mut Option<F::Output>,
+    cx: &Context<'_>,
+) {
+    if out.is_none() && ready & (1 << branch) != 0 {
+        let waker = tracker.waker(branch, cx);
+        if let Poll::Ready(v) = fut.poll(&mut Context::from_waker(&waker)) {
+            *out = Some(v);
+        }
+    }
+}
+
+macro_rules! joiner {
+    (
+        $(#[$attr:meta])*
+        $fn:ident, $name:ident, $(($F:ident, $f:ident, $out:ident, $i:tt)),*
+    ) => {
+        pin_project! {
+            /// Future returned by the function of the same name; see its docs.
+            #[derive(Debug)]
+            #[must_use = "futures do nothing unless you `.await` or poll them"]
+            pub struct $name<$($F: Future),*> {
+                tracker: Tracker,
+                $(
+                    #[pin]
+                    $f: $F,
+                    $out: Option<$F::Output>,
+                )*
+            }
+        }
+
+        $(#[$attr])*
+        pub fn $fn<$($F: Future),*>($($f: $F),*) -> $name<$($F),*> {
+            $name {
+                tracker: Tracker::default(),
+                $($f, $out: None,)*
+            }
+        }
+
+        impl<$($F: Future),*> Future for $name<$($F),*> {
+            type Output = ($($F::Output,)*);
+
+            fn poll(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<Self::Output> {
+                let p = self.project();
+                let ready = p.tracker.begin_poll(cx);
+                $(
+                    poll_branch(p.tracker, $i, ready, p.$f, p.$out, cx);
+                )*
+                if $(p.$out.is_some())&&* {
+                    p.tracker.release();
+                    Poll::Ready(($(p.$out.take().unwrap(),)*))
+                } else {
+                    Poll::Pending
+                }
+            }
+        }
+    };
+}
+
+joiner! {
+    /// Runs two futures concurrently, resolving to both of their results once
+    /// both have finished. Each is only polled when something has woken it.
+    ///
+    /// This is a drop-in replacement for `futures::future::join`, aside from
+    /// the more precise wakeups.
+    join, Join, (A, a, a_out, 0), (B, b, b_out, 1)
+}
+
+joiner! {
+    /// Three-future version of [`join`].
+    join3, Join3, (A, a, a_out, 0), (B, b, b_out, 1), (C, c, c_out, 2)
+}
+
+joiner! {
+    /// Four-future version of [`join`].
+    join4, Join4,
+    (A, a, a_out, 0), (B, b, b_out, 1), (C, c, c_out, 2), (D, d, d_out, 3)
+}
+
+/// Result of [`select`], indicating which future finished first.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Either<A, B> {
+    /// The first future finished.
+    Left(A),
+    /// The second future finished.
+    Right(B),
+}
+
+pin_project! {
+    /// Future returned by [`select`].
+    #[derive(Debug)]
+    #[must_use = "futures do nothing unless you `.await` or poll them"]
+    pub struct Select<A, B> {
+        tracker: Tracker,
+        #[pin]
+        a: A,
+        #[pin]
+        b: B,
+    }
+}
+
+/// Runs two futures concurrently until either one finishes, and resolves to
+/// its result. The other future is dropped along with the `Select`. Each is
+/// only polled when something has woken it.
+///
+/// If both are ready at once, `a` wins, as with `futures::select_biased!`.
+pub fn select<A: Future, B: Future>(a: A, b: B) -> Select<A, B> {
+    Select {
+        tracker: Tracker::default(),
+        a,
+        b,
+    }
+}
+
+impl<A: Future, B: Future> Future for Select<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let p = self.project();
+        let ready = p.tracker.begin_poll(cx);
+        let mut a_out = None;
+        poll_branch(p.tracker, 0, ready, p.a, &mut a_out, cx);
+        if let Some(v) = a_out {
+            p.tracker.release();
+            return Poll::Ready(Either::Left(v));
+        }
+        let mut b_out = None;
+        poll_branch(p.tracker, 1, ready, p.b, &mut b_out, cx);
+        if let Some(v) = b_out {
+            p.tracker.release();
+            return Poll::Ready(Either::Right(v));
+        }
+        Poll::Pending
+    }
+}
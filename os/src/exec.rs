@@ -22,8 +22,22 @@
 //! - If you need code to run when no other tasks are ready -- which can be
 //! useful for putting the CPU into a low power state, or toggling a pin to
 //! signal CPU load on a logic analyzer -- see [`run_tasks_with_idle`]
-//! - Finally, if you want to turn on all the bells and whistles, you can use
+//! - If you want to turn on all the bells and whistles, you can use
 //! [`run_tasks_with_preemption_and_idle`] which combines the previous two.
+//! - Finally, if some tasks need to respond more urgently than others, you can
+//! switch the executor into priority order using [`run_tasks_with_options`];
+//! see [Scheduling](#scheduling) below.
+//!
+//! All of these are shorthand for configurations of the [`Executor`] builder,
+//! which is what to reach for when you need a combination they don't cover --
+//! say, an idle task, priority scheduling, and statistics all at once:
+//!
+//! ```ignore
+//! Executor::new(&mut [a, b, c])
+//!     .scheduling(Scheduling::Priority)
+//!     .idle_task(background)
+//!     .run()
+//! ```
 //!
 //!
 //! # Interrupts, wait, and notify
@@ -77,10 +91,29 @@
 //! using this to handle events from a UART.
 //!
 //!
+//! # Scheduling
+//!
+//! By default, each time through the outer poll loop, the executor polls every
+//! task whose wake bit was set, in index order. Any tasks woken _during_ that
+//! pass get polled on the next one. This is simple and fair, but it means that
+//! a task woken by a lower-numbered task (or an ISR) has to wait behind every
+//! other task that was already pending.
+//!
+//! If you pass [`Scheduling::Priority`] to [`run_tasks_with_options`], the
+//! executor instead treats the task index as a priority, with index 0 being
+//! most important. After _each_ task poll, it rechecks the wake bits and polls
+//! the highest-priority ready task. This way, an urgent task never waits more
+//! than one poll of some other task before it runs. The tradeoff is that a
+//! high-priority task that never stops waking itself will starve
+//! lower-priority tasks completely.
+//!
+//!
 //! # Idle behavior
 //!
 //! When no tasks have their wake bits set, the default behavior is to idle the
-//! processor using the `WFI` instruction. You can override this behavior by
+//! processor using the `WFI` instruction. (With the `hosted` feature, the
+//! executor thread is parked instead, until another thread wakes a task.) You
+//! can override this behavior by
 //! starting the scheduler with [`run_tasks_with_idle`] or (if you're using
 //! preemption, below) [`run_tasks_with_preemption_and_idle`], which let you
 //! substitute a custom "idle hook" to execute when no tasks are ready.
@@ -89,6 +122,17 @@
 //! on a logic analyzer, enter a vendor-specific deep-sleep mode, or feed a
 //! watchdog.
 //!
+//! The idle hook is a plain function, so it can't `await` anything. If you've
+//! got background work that's best written as async code -- scrubbing memory,
+//! say, or uploading statistics -- start the scheduler with
+//! [`run_tasks_with_idle_task`] instead. It takes an extra task that's only
+//! polled when no other task is ready.
+//!
+//! To make a better decision about how to idle, use
+//! [`run_tasks_with_idle_context`]. Its idle hook gets an [`IdleContext`],
+//! which says when the next timer is due. A power manager can use this to
+//! choose between a quick `WFI` and a deep sleep with a wake-up timer.
+//!
 //!
 //! # Building your own task notification mechanism
 //!
@@ -99,17 +143,91 @@
 //! energy and time.
 //!
 //! Both of these functions expose the fact that the scheduler tracks wake bits
-//! in a single `usize`. When waking a task with index 0 (mask `1 << 0`), we're
+//! in a fixed-size bitmap, described by the [`WakeMask`] type. By default, it's
+//! a single `usize`. When waking a task with index 0 (mask `1 << 0`), we're
 //! actually waking any task where `index % 32 == 0`. Very complex systems with
 //! greater than 32 top-level tasks will thus experience more spurious wakeups.
 //! The advantage of this "lossy" technique is that wake bit manipulation is
 //! very, very cheap, and can be done entirely with processor atomic operations.
 //!
+//! If your application has more tasks than that, you can make the bitmap
+//! larger by enabling one of the `wake-bits-64`, `wake-bits-128`, or
+//! `wake-bits-256` features. Tasks with indices below the chosen size then get
+//! exact wakeups (at the cost of a few more words of RAM in each `Notify`, and
+//! a few more instructions when waking). Build a `WakeMask` naming the
+//! specific tasks you want using [`WakeMask::with_task`].
+//!
 //! For an example of how to do this, read the source code for `Notify` -- it's
 //! written entirely in terms of public API, so if you want to do something
 //! similar that `Notify` itself doesn't support, you can start by copying it.
 //!
 //!
+//! # Naming tasks
+//!
+//! Waking a task by index means agreeing with `main` about the order of the
+//! task array, and quietly waking the wrong task if someone reorders it. The
+//! [`task_table!`][crate::task_table] macro instead declares the tasks by
+//! name, as a module of [`TaskId`] constants that everything else can refer
+//! to:
+//!
+//! ```ignore
+//! lilos::task_table! {
+//!     /// Our tasks, in the order they're passed to the executor.
+//!     pub mod tasks {
+//!         uart_rx,
+//!         blinky,
+//!         housekeeping,
+//!     }
+//! }
+//! ```
+//!
+//! [`run_task_table!`][crate::run_task_table] then starts the executor with
+//! one future per name, checking at compile time that they're in the same
+//! order as the table:
+//!
+//! ```ignore
+//! lilos::run_task_table! {
+//!     tasks {
+//!         uart_rx: uart_rx_task(&UART),
+//!         blinky: blink(&LED),
+//!         housekeeping: housekeeping(),
+//!     }
+//!     start: [uart_rx, blinky]
+//! }
+//! ```
+//!
+//! Anything else, including an ISR, can then use the names:
+//!
+//! ```ignore
+//! use crate::tasks::*;
+//!
+//! lilos::wake_task!(uart_rx);
+//! exec::wake_tasks_by_mask(lilos::task_mask!(blinky, housekeeping));
+//! ```
+//!
+//! These are plain `macro_rules!` macros, like the rest of `lilos`; no proc
+//! macros are involved.
+//!
+//!
+//! # Suspending and starting tasks
+//!
+//! The initial mask passed to `run_tasks` decides which tasks get polled at
+//! startup; the rest wait until something wakes them. For more control, such as
+//! turning off a sensor task while the system is in a low-power mode, enable
+//! the `task-control` feature. This adds `suspend_task` and `resume_task`,
+//! which hold off a task's wakeups and then deliver them, and `stop_task` and
+//! `start_task`, which discard them and then start the task regardless.
+//!
+//!
+//! # Restarting tasks
+//!
+//! A task that's gotten itself wedged can be replaced without a heap, if it's
+//! put in a [`RestartableTask`] slot. The slot builds the task's future by
+//! calling a factory closure, and on request (through a [`RestartControl`])
+//! drops it and builds a fresh one in its place. See the `RestartableTask`
+//! docs for an example.
+//!
+//!
 //! # Adding preemption
 //!
 //! By default, the scheduler does not preempt task code: task poll routines are
@@ -123,12 +241,55 @@
 //! [`run_tasks_with_preemption`] or [`run_tasks_with_preemption_and_idle`].
 //! These entry points let you set a _preemption policy_, which allows ISRs
 //! above some priority level to preempt task code. (Tasks still cannot preempt
-//! one another.)
+//! one another, unless you use an interrupt executor; see below.)
 //!
 //! The more basic [`run_tasks`] operation is written in terms of
 //! [`run_tasks_with_preemption_and_idle`], so if you would like to see how to
 //! convert your use of `run_tasks` to the more complex form, start by copying
 //! the code from `run_tasks`.
+//!
+//!
+//! # Interrupt executors
+//!
+//! If some tasks can't wait for a long poll of another task to finish, enable
+//! the `interrupt-executor` feature. This lets you run those tasks on an
+//! `InterruptExecutor`, which polls them from inside an interrupt handler
+//! (such as PendSV) that can preempt the rest of the tasks. They can still use
+//! `Notify`, `spsc` queues, and timers. See the `InterruptExecutor` docs for
+//! how to set one up, and what it means for data shared between tasks.
+//!
+//!
+//! # Task statistics
+//!
+//! If you're trying to find out which task is eating your CPU time, enable the
+//! `stats` feature. This lets you provide a `TaskStatsTable` to
+//! `enable_task_stats`, after which the executor records the number of polls
+//! and the total and worst-case poll time of each task. See `task_stats` for
+//! how to read the results.
+//!
+//!
+//! # Tracing
+//!
+//! To see what the executor is doing over time, enable the `trace` feature
+//! and install a `TraceSink` with `set_trace_sink`. The executor will report
+//! task polls, wakeups, idle periods, and timer expirations to it. The
+//! provided `TraceRing` sink keeps the most recent events in RAM for
+//! inspection after the fact.
+//!
+//!
+//! # Poll budgets
+//!
+//! A task that runs for a long time without yielding delays all the others.
+//! To catch this during testing, enable the `poll-budget` feature and give the
+//! executor a budget with `set_poll_budget`, or the `Executor` builder's
+//! `poll_budget`. Any task poll that takes longer is reported to a hook of your
+//! choosing, along with the index of the task responsible.
+//!
+//! Once you've found such a task, if it can't be made to do less work per
+//! poll, it may need to yield partway through. `yield_cpu` always yields,
+//! which can be expensive in a tight loop; with the `time-slice` feature,
+//! `maybe_yield` yields only once the task has run longer than the slice set
+//! with `set_time_slice`, or the `Executor` builder's `time_slice`.
 
 use core::convert::Infallible;
 use core::future::Future;
@@ -139,13 +300,79 @@ use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
 
 use pin_project_lite::pin_project;
 
+use crate::arch;
 use crate::atomic::{AtomicExt, AtomicArithExt};
 
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(feature = "stats")]
+pub use stats::{
+    enable_task_stats, reset_task_stats, task_stats, TaskStats, TaskStatsTable,
+};
+
+#[cfg(feature = "trace")]
+mod trace;
+#[cfg(feature = "trace")]
+pub use trace::{
+    set_trace_sink, TraceEvent, TraceRecord, TraceRing, TraceSink,
+};
+
+#[cfg(feature = "poll-budget")]
+mod budget;
+#[cfg(feature = "poll-budget")]
+pub use budget::{clear_poll_budget, set_poll_budget, PollOverrun};
+
+#[cfg(feature = "time-slice")]
+mod slice;
+#[cfg(feature = "time-slice")]
+pub use slice::{clear_time_slice, maybe_yield, set_time_slice};
+
+mod builder;
+pub use builder::Executor;
+use builder::IdleTask;
+
+mod restart;
+pub use restart::{RestartControl, RestartableTask};
+
+mod table;
+pub use table::TaskId;
+
+#[cfg(feature = "interrupt-executor")]
+mod interrupt;
+#[cfg(feature = "interrupt-executor")]
+pub use interrupt::InterruptExecutor;
+#[cfg(all(feature = "interrupt-executor", feature = "time"))]
+pub(crate) use interrupt::pend_timer_executors;
+
+/// Reads a free-running counter for timing task polls and trace events. See
+/// the `stats` module for a discussion of the units.
+#[cfg(any(
+    feature = "stats",
+    feature = "trace",
+    feature = "poll-budget",
+    feature = "time-slice",
+))]
+#[inline(always)]
+fn timestamp() -> u32 {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "has-cyccnt")] {
+            cortex_m::peripheral::DWT::cycle_count()
+        } else if #[cfg(all(target_arch = "riscv32", not(feature = "hosted")))] {
+            riscv::register::mcycle::read() as u32
+        } else if #[cfg(feature = "time")] {
+            // Truncation is fine; we only ever take differences.
+            u64::from(TickTime::now()) as u32
+        } else {
+            0
+        }
+    }
+}
+
 // Despite the untangling of exec and time that happened in the 1.0 release, we
 // still have some intimate dependencies between the modules. You'll see a few
-// other cfg(feature = "systick") lines below.
+// other cfg(feature = "time") lines below.
 cfg_if::cfg_if! {
-    if #[cfg(feature = "systick")] {
+    if #[cfg(feature = "time")] {
         use core::sync::atomic::AtomicPtr;
 
         use crate::cheap_assert;
@@ -154,58 +381,256 @@ cfg_if::cfg_if! {
     }
 }
 
-/// Accumulates bitmasks from wakers as they are invoked. The executor
-/// atomically checks and clears this at each iteration.
-static WAKE_BITS: AtomicUsize = AtomicUsize::new(0);
-
-/// Computes the wake bit mask for the task with the given index, which is
-/// equivalent to `1 << (index % USIZE_BITS)`.
-const fn wake_mask_for_index(index: usize) -> usize {
-    // Lossy as-cast used here because rotate_left implies a mod by small power
-    // of 2 (32, 64) and `as u32` implies mod by 2**32, which doesn't change the
-    // result.
-    1_usize.rotate_left(index as u32)
+/// Number of bits in a `usize`, and thus in each word of a `WakeMask`.
+const USIZE_BITS: usize = usize::BITS as usize;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "wake-bits-256")] {
+        const WAKE_BITS_COUNT: usize = 256;
+    } else if #[cfg(feature = "wake-bits-128")] {
+        const WAKE_BITS_COUNT: usize = 128;
+    } else if #[cfg(feature = "wake-bits-64")] {
+        const WAKE_BITS_COUNT: usize = 64;
+    } else {
+        const WAKE_BITS_COUNT: usize = USIZE_BITS;
+    }
+}
+
+/// Number of words used to record wake bits. This is always at least 1, even
+/// if a `wake-bits-N` feature asks for fewer bits than are in a `usize` (which
+/// can happen on 64-bit hosted builds).
+const WAKE_WORDS: usize = (WAKE_BITS_COUNT + USIZE_BITS - 1) / USIZE_BITS;
+
+/// Finds the word and bit that represent the task with the given index in a
+/// `WakeMask`. Indices past the end of the bitmap wrap around.
+#[inline(always)]
+const fn wake_bit_for_index(index: usize) -> (usize, usize) {
+    let index = index % (WAKE_WORDS * USIZE_BITS);
+    (index / USIZE_BITS, 1 << (index % USIZE_BITS))
+}
+
+/// A set of tasks, identified by their indices in the array passed to
+/// [`run_tasks`]. This is used to choose which tasks to start, or to wake.
+///
+/// `WakeMask` is a bitmap with one bit per task. By default it's a single
+/// `usize`, but it can be made larger with the `wake-bits-N` features (see the
+/// [module docs](self#building-your-own-task-notification-mechanism)). Tasks
+/// whose indices don't fit in the bitmap share bits with lower-numbered tasks,
+/// so naming task `n` will also name any task `m` where `n` and `m` are equal
+/// modulo the size of the bitmap. This can cause spurious wakeups, but never
+/// missed ones.
+///
+/// For compatibility, and convenience, a plain `usize` converts into a
+/// `WakeMask`. Each bit `b` in the `usize` names every task whose index is
+/// equal to `b` modulo the number of bits in a `usize` -- which is exactly what
+/// it meant before `WakeMask` existed. In particular, [`ALL_TASKS`] names every
+/// task regardless of the size of the bitmap.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Default)]
+pub struct WakeMask([usize; WAKE_WORDS]);
+
+impl WakeMask {
+    /// Creates a `WakeMask` that doesn't name any tasks.
+    pub const fn new() -> Self {
+        Self([0; WAKE_WORDS])
+    }
+
+    /// Returns a copy of this mask that also names the task with the given
+    /// index. This is a `const fn`, so you can use it to build masks in
+    /// constants:
+    ///
+    /// ```ignore
+    /// const START: WakeMask = WakeMask::new().with_task(0).with_task(40);
+    /// ```
+    pub const fn with_task(mut self, index: usize) -> Self {
+        let (word, bit) = wake_bit_for_index(index);
+        self.0[word] |= bit;
+        self
+    }
+
+    /// Checks whether this mask names the task with the given index.
+    pub fn contains(&self, index: usize) -> bool {
+        let (word, bit) = wake_bit_for_index(index);
+        self.0[word] & bit != 0
+    }
+
+    /// Checks whether this mask doesn't name any tasks.
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&w| w == 0)
+    }
+}
+
+impl From<usize> for WakeMask {
+    fn from(mask: usize) -> Self {
+        Self([mask; WAKE_WORDS])
+    }
+}
+
+/// Atomic version of `WakeMask`, for accumulating wakeups from any context.
+///
+/// Each word is updated atomically, but the mask as a whole is not; that's
+/// fine, since a bit that misses one `take` will be caught by the next.
+#[derive(Debug)]
+struct AtomicWakeMask([AtomicUsize; WAKE_WORDS]);
+
+impl AtomicWakeMask {
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicUsize = AtomicUsize::new(0);
+        Self([ZERO; WAKE_WORDS])
+    }
+
+    /// Adds the task with the given index.
+    #[inline(always)]
+    fn insert(&self, index: usize) {
+        let (word, bit) = wake_bit_for_index(index);
+        self.0[word].fetch_or_polyfill(bit, Ordering::SeqCst);
+    }
+
+    /// Adds all the tasks named in `mask`.
+    #[inline(always)]
+    fn insert_all(&self, mask: WakeMask) {
+        for (a, &m) in self.0.iter().zip(&mask.0) {
+            if m != 0 {
+                a.fetch_or_polyfill(m, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Atomically clears each word of the mask, returning the previous
+    /// contents.
+    #[inline(always)]
+    fn take(&self) -> WakeMask {
+        let mut mask = WakeMask::new();
+        for (m, a) in mask.0.iter_mut().zip(&self.0) {
+            *m = a.swap_polyfill(0, Ordering::SeqCst);
+        }
+        mask
+    }
+
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(|a| a.load(Ordering::SeqCst) == 0)
+    }
+
+    /// Returns a snapshot of the mask, without changing it.
+    #[cfg(feature = "task-control")]
+    #[inline(always)]
+    fn load(&self) -> WakeMask {
+        let mut mask = WakeMask::new();
+        for (m, a) in mask.0.iter_mut().zip(&self.0) {
+            *m = a.load(Ordering::SeqCst);
+        }
+        mask
+    }
+
+    /// Removes the task with the given index (and anything aliased to it),
+    /// returning whether it was there.
+    #[cfg(feature = "task-control")]
+    #[inline(always)]
+    fn remove(&self, index: usize) -> bool {
+        let (word, bit) = wake_bit_for_index(index);
+        self.0[word].fetch_and_polyfill(!bit, Ordering::SeqCst) & bit != 0
+    }
+
+    /// Removes all the tasks named in `mask`.
+    #[cfg(feature = "task-control")]
+    #[inline(always)]
+    fn remove_all(&self, mask: WakeMask) {
+        for (a, &m) in self.0.iter().zip(&mask.0) {
+            if m != 0 {
+                a.fetch_and_polyfill(!m, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl WakeMask {
+    /// Returns the index of the lowest-numbered task in `0..count` that's
+    /// named in this mask.
+    fn first_in(&self, count: usize) -> Option<usize> {
+        (0..count).find(|&i| self.contains(i))
+    }
+
+    /// Removes the task with the given index (and anything aliased to it).
+    fn remove(&mut self, index: usize) {
+        let (word, bit) = wake_bit_for_index(index);
+        self.0[word] &= !bit;
+    }
+
+    /// Adds all the tasks named in `other`.
+    fn insert_all(&mut self, other: WakeMask) {
+        for (m, &o) in self.0.iter_mut().zip(&other.0) {
+            *m |= o;
+        }
+    }
+
+    /// Returns the tasks named in both this mask and `other`.
+    #[cfg(feature = "task-control")]
+    fn intersection(mut self, other: WakeMask) -> WakeMask {
+        for (m, &o) in self.0.iter_mut().zip(&other.0) {
+            *m &= o;
+        }
+        self
+    }
+
+    /// Returns the tasks named in this mask but not in `other`.
+    #[cfg(feature = "task-control")]
+    fn difference(mut self, other: WakeMask) -> WakeMask {
+        for (m, &o) in self.0.iter_mut().zip(&other.0) {
+            *m &= !o;
+        }
+        self
+    }
 }
 
-/// VTable for our wakers. Our wakers store a task notification bitmask in their
-/// "pointer" member, and atomically OR it into `WAKE_BITS` when invoked.
+impl Default for AtomicWakeMask {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Accumulates wake bits from wakers as they are invoked. The executor
+/// atomically checks and clears this at each iteration.
+static WAKE_BITS: AtomicWakeMask = AtomicWakeMask::new();
+
+/// VTable for our wakers. Our wakers store a task index in their "pointer"
+/// member, and atomically set the corresponding bit in `WAKE_BITS` when
+/// invoked.
 static VTABLE: RawWakerVTable = RawWakerVTable::new(
     // clone
     |p| RawWaker::new(p, &VTABLE),
     // wake
-    |p| wake_tasks_by_mask(p as usize),
+    |p| wake_task_by_index(p as usize),
     // wake_by_ref
-    |p| wake_tasks_by_mask(p as usize),
+    |p| wake_task_by_index(p as usize),
     // drop
     |_| (),
 );
 
 /// Produces a `Waker` that will wake *at least* task `index` on invocation.
 ///
-/// Technically, this will wake any task `n` where `n % 32 == index % 32`.
+/// Technically, this will wake any task that shares a wake bit with `index`
+/// (see [`WakeMask`]).
 fn waker_for_task(index: usize) -> Waker {
-    let mask = wake_mask_for_index(index);
     // Safety: Waker::from_raw is unsafe because bad things happen if the
     // combination of this particular pointer and the functions in the vtable
     // don't meet the Waker contract or are incompatible. In our case, our
     // vtable functions are actually entirely safe, since we're passing an
     // integer as a pointer.
     unsafe {
-        Waker::from_raw(RawWaker::new(mask as *const (), &VTABLE))
+        Waker::from_raw(RawWaker::new(index as *const (), &VTABLE))
     }
 }
 
-/// Exploits our known Waker structure to extract the notification mask from a
-/// Waker.
+/// Exploits our known Waker structure to extract the task index from a Waker.
 ///
 /// If this is applied to a Waker that isn't from this executor (specifically,
-/// one not generated by `waker_for_task`), this will cause spurious and almost
-/// certainly incorrect wakeups. Currently I don't feel like that risk is great
-/// enough to mark this unsafe -- it can't violate *memory* safety for certain.
+/// one not generated by `waker_for_task`), this will return garbage. Use
+/// `task_index_of` if you don't know where the Waker came from.
 ///
 /// In practice this function compiles down to a single inlined load
 /// instruction.
-fn extract_mask(waker: &Waker) -> usize {
+pub(crate) fn extract_index(waker: &Waker) -> usize {
     // Determine whether the pointer member comes first or second within the
     // representation of RawWaker. This is currently compile-time simplified
     // and goes away.
@@ -240,6 +665,23 @@ fn extract_mask(waker: &Waker) -> usize {
     }
 }
 
+/// Returns the index of the task that `waker` wakes, if it came from this
+/// executor, or `None` if it's a "foreign" waker from somewhere else -- another
+/// executor, or a combinator that wraps wakers, for instance.
+///
+/// This works by rebuilding what our waker for that task would look like, and
+/// comparing the two, so that if `extract_index` gets it wrong we'll fail
+/// safe and treat the waker as foreign.
+#[inline(always)]
+pub(crate) fn task_index_of(waker: &Waker) -> Option<usize> {
+    let index = extract_index(waker);
+    if waker.will_wake(&waker_for_task(index)) {
+        Some(index)
+    } else {
+        None
+    }
+}
+
 /// Used to construct wakers do nothing, as a placeholder.
 static NOOP_VTABLE: RawWakerVTable = RawWakerVTable::new(
     |x| RawWaker::new(x, &NOOP_VTABLE), // clone
@@ -266,16 +708,62 @@ fn poll_task(
     index: usize,
     future: Pin<&mut dyn Future<Output = Infallible>>,
 ) {
+    #[cfg(feature = "trace")]
+    trace::emit(TraceEvent::PollStart(index));
+    #[cfg(feature = "stats")]
+    let start = stats::poll_started(index);
+    #[cfg(feature = "poll-budget")]
+    let budget_start = budget::poll_started();
+    #[cfg(feature = "time-slice")]
+    let outer_slice = slice::poll_started();
+    #[cfg(feature = "task-isolation")]
+    let outer_isolation = crate::isolation::poll_started(
+        index,
+        crate::isolation::Region::of(future.as_ref().get_ref()),
+    );
+
     match future.poll(&mut Context::from_waker(&waker_for_task(index))) {
         Poll::Pending => (),
         Poll::Ready(never) => match never {}
     }
+
+    #[cfg(feature = "task-isolation")]
+    crate::isolation::poll_finished(index, outer_isolation);
+    #[cfg(feature = "time-slice")]
+    slice::poll_finished(outer_slice);
+    #[cfg(feature = "stats")]
+    stats::poll_finished(index, start);
+    #[cfg(feature = "trace")]
+    trace::emit(TraceEvent::PollEnd(index));
+    // Last, so that the poll is fully accounted for before the hook runs.
+    #[cfg(feature = "poll-budget")]
+    budget::poll_finished(index, budget_start);
+}
+
+/// Selects the order in which the scheduler polls tasks that are ready.
+///
+/// This is used as an argument to [`run_tasks_with_options`]. See the
+/// [module docs](self#scheduling) for a discussion of the tradeoffs.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Scheduling {
+    /// Each time through the loop, poll all ready tasks in index order. Tasks
+    /// woken during this process are polled on the next pass. This is the
+    /// behavior of [`run_tasks`].
+    #[default]
+    RoundRobin,
+    /// Treat task indices as priorities, with 0 being the highest. After every
+    /// task poll, check for newly woken tasks and poll the highest-priority
+    /// ready task next.
+    ///
+    /// Tasks whose indices alias in the wake bitmap (see [`WakeMask`]) share a
+    /// priority, that of the lowest-numbered task in the group.
+    Priority,
 }
 
 /// Selects an interrupt control strategy for the scheduler.
 ///
-/// This is used as an argument to [`run_tasks_with_preemption`] and
-/// [`run_tasks_with_preemption_and_idle`].
+/// This is used as an argument to [`run_tasks_with_preemption`],
+/// [`run_tasks_with_preemption_and_idle`], and [`run_tasks_with_options`].
 #[derive(Copy, Clone, Debug)]
 pub enum Interrupts {
     /// Use PRIMASK to completely disable interrupts while task code is running.
@@ -298,7 +786,8 @@ pub enum Interrupts {
     /// is enough to stop losing ticks during long-running sequences. (You can
     /// adjust this priority in the NVIC.)
     ///
-    /// This is not available on ARMv6-M, which lacks the `BASEPRI` feature.
+    /// This is not available on ARMv6-M or ARMv8-M baseline, which lack the
+    /// `BASEPRI` feature, or on RISC-V.
     #[cfg(feature = "has-basepri")]
     Filtered(u8),
 }
@@ -306,40 +795,15 @@ pub enum Interrupts {
 impl Interrupts {
     fn scope<R>(self, body: impl FnOnce() -> R) -> R {
         let r = match self {
-            Interrupts::Masked => {
-                let prev = cortex_m::register::primask::read();
-                cortex_m::interrupt::disable();
-
-                let r = body();
-
-                if prev == cortex_m::register::primask::Primask::Active {
-                    // Safety: interrupts were just on, so this won't compromise
-                    // memory safety.
-                    unsafe {
-                        cortex_m::interrupt::enable();
-                    }
-                }
-
-                r
-            }
+            Interrupts::Masked => arch::with_interrupts_masked(body),
             #[cfg(feature = "has-basepri")]
             Interrupts::Filtered(priority) => {
-                let prev = cortex_m::register::basepri::read();
-                cortex_m::register::basepri_max::write(priority);
-
-                let r = body();
-
-                // Safety: just restoring state
-                unsafe {
-                    cortex_m::register::basepri::write(prev);
-                }
-
-                r
+                arch::with_interrupts_filtered(priority, body)
             }
         };
 
         // Make sure newly-enabled interrupt handlers fire.
-        cortex_m::asm::isb();
+        arch::isb();
 
         r
     }
@@ -355,9 +819,10 @@ impl Interrupts {
 /// awkward way of writing `!`.
 ///
 /// Not all tasks are polled every time through the loop. On the first
-/// iteration, only the tasks with a corresponding bit set in `initial_mask` are
-/// polled; on subsequent passes, only tasks awoken by the *previous* iteration
-/// are called.
+/// iteration, only the tasks named in `initial_mask` are polled; on subsequent
+/// passes, only tasks awoken by the *previous* iteration are called.
+/// `initial_mask` can be a [`WakeMask`], or a `usize` for the common case of a
+/// handful of tasks (see `WakeMask` for how those bits are interpreted).
 ///
 /// Any time polling completes with *no* tasks awoken, code will never run again
 /// unless an interrupt handler wakes tasks using `Notify`. And so, when we
@@ -367,32 +832,26 @@ impl Interrupts {
 /// override this behavior, see [`run_tasks_with_idle`].
 pub fn run_tasks(
     futures: &mut [Pin<&mut dyn Future<Output = Infallible>>],
-    initial_mask: usize,
+    initial_mask: impl Into<WakeMask>,
 ) -> ! {
-    // Safety: we're passing Interrupts::Masked, the always-safe option
-    unsafe {
-        run_tasks_with_preemption_and_idle(
-            futures,
-            initial_mask,
-            Interrupts::Masked,
-            || {
-                cortex_m::asm::wfi();
-                // This works around an undocumented erratum on STM32 processors
-                // when WFI is set to go to "Sleep" level, and a debug agent has
-                // set the DBGMCU bits to cause clocks to continue to run during
-                // sleep. In this situation, it appears that the pipeline state
-                // after the WFI can be corrupted in the specific case where the
-                // WFI happens _without_ an interrupt service routine occurring
-                // (i.e. our default configuration of interrupts masked). An ISB
-                // appears to fix it, independent of alignment etc.
-                //
-                // Hard to tell, though, since this isn't in the errata sheet.
-                //
-                // On non-STM32 Cortex processors this will cost a few cycles.
-                cortex_m::asm::isb();
-            },
-        )
-    }
+    Executor::new(futures).initial_mask(initial_mask).run()
+}
+
+/// The idle hook used by `run_tasks`: sleeps until the next interrupt.
+fn default_idle_hook() {
+    arch::wait_for_interrupt();
+    // This works around an undocumented erratum on STM32 processors when WFI
+    // is set to go to "Sleep" level, and a debug agent has set the DBGMCU bits
+    // to cause clocks to continue to run during sleep. In this situation, it
+    // appears that the pipeline state after the WFI can be corrupted in the
+    // specific case where the WFI happens _without_ an interrupt service
+    // routine occurring (i.e. our default configuration of interrupts masked).
+    // An ISB appears to fix it, independent of alignment etc.
+    //
+    // Hard to tell, though, since this isn't in the errata sheet.
+    //
+    // On non-STM32 Cortex processors this will cost a few cycles.
+    arch::isb();
 }
 
 /// Extended version of `run_tasks` that replaces the default idle behavior
@@ -404,18 +863,53 @@ pub fn run_tasks(
 /// See [`run_tasks`] for more details.
 pub fn run_tasks_with_idle(
     futures: &mut [Pin<&mut dyn Future<Output = Infallible>>],
-    initial_mask: usize,
+    initial_mask: impl Into<WakeMask>,
     idle_hook: impl FnMut(),
 ) -> ! {
-    // Safety: we're passing Interrupts::Masked, the always-safe option
-    unsafe {
-        run_tasks_with_preemption_and_idle(
-            futures,
-            initial_mask,
-            Interrupts::Masked,
-            idle_hook,
-        )
-    }
+    Executor::new(futures)
+        .initial_mask(initial_mask)
+        .idle_hook(idle_hook)
+        .run()
+}
+
+/// Extended version of `run_tasks` that runs an extra, lowest-priority task,
+/// `idle_task`, when none of the others are ready.
+///
+/// The idle task is polled only when a pass through the task list leaves no
+/// other task woken, and only if it's been woken itself (it starts out woken,
+/// so it gets polled once when the executor first goes idle). It has the task
+/// index `futures.len()`, so it can use `Notify`, timers, and everything else
+/// ordinary tasks can. When it yields, the executor checks for other work
+/// before polling it again, so it's a good place for background work that
+/// should give way the moment anything else needs doing:
+///
+/// ```ignore
+/// let scrubber = pin!(async {
+///     loop {
+///         check_next_flash_block();
+///         // Give real work a chance to run.
+///         lilos::exec::yield_cpu().await;
+///     }
+/// });
+/// lilos::exec::run_tasks_with_idle_task(&mut [a, b], lilos::exec::ALL_TASKS, scrubber);
+/// ```
+///
+/// If nothing is ready, including the idle task, the executor sleeps just as
+/// `run_tasks` does.
+///
+/// If you're also using [interrupt executors](self#interrupt-executors), keep
+/// in mind that the idle task's index counts as one of this executor's tasks.
+///
+/// See [`run_tasks`] for more details.
+pub fn run_tasks_with_idle_task(
+    futures: &mut [Pin<&mut dyn Future<Output = Infallible>>],
+    initial_mask: impl Into<WakeMask>,
+    idle_task: Pin<&mut dyn Future<Output = Infallible>>,
+) -> ! {
+    Executor::new(futures)
+        .initial_mask(initial_mask)
+        .idle_task(idle_task)
+        .run()
 }
 
 /// Extended version of `run_tasks` that configures the scheduler with a custom
@@ -440,18 +934,17 @@ pub fn run_tasks_with_idle(
 /// as being ISR safe, such as `Notify::notify`, can be used from ISRs.
 pub unsafe fn run_tasks_with_preemption(
     futures: &mut [Pin<&mut dyn Future<Output = Infallible>>],
-    initial_mask: usize,
+    initial_mask: impl Into<WakeMask>,
     interrupts: Interrupts,
 ) -> ! {
     // Safety: this is safe if our own contract is upheld.
     unsafe {
-        run_tasks_with_preemption_and_idle(
-            futures,
-            initial_mask,
-            interrupts,
-            cortex_m::asm::wfi,
-        )
+        Executor::new(futures)
+            .initial_mask(initial_mask)
+            .interrupts(interrupts)
     }
+    .idle_hook(arch::wait_for_interrupt)
+    .run()
 }
 
 /// Extended version of `run_tasks` that configures the scheduler with a custom
@@ -479,9 +972,127 @@ pub unsafe fn run_tasks_with_preemption(
 /// as being ISR safe, such as `Notify::notify`, can be used from ISRs.
 pub unsafe fn run_tasks_with_preemption_and_idle(
     futures: &mut [Pin<&mut dyn Future<Output = Infallible>>],
-    initial_mask: usize,
+    initial_mask: impl Into<WakeMask>,
+    interrupts: Interrupts,
+    idle_hook: impl FnMut(),
+) -> ! {
+    // Safety: this is safe if our own contract is upheld.
+    unsafe {
+        Executor::new(futures)
+            .initial_mask(initial_mask)
+            .interrupts(interrupts)
+    }
+    .idle_hook(idle_hook)
+    .run()
+}
+
+/// Most general version of `run_tasks`, which configures the scheduler with a
+/// custom interrupt policy, scheduling order, and idle hook. See [`run_tasks`]
+/// for more information about the basic behavior.
+///
+/// Passing `Scheduling::RoundRobin` here gets the same behavior as
+/// [`run_tasks_with_preemption_and_idle`]. Passing `Scheduling::Priority`
+/// causes the scheduler to poll ready tasks in priority order, as described in
+/// the docs for [`Scheduling`].
+///
+/// # Safety
+///
+/// This has the same contract as [`run_tasks_with_preemption_and_idle`]; if
+/// you're passing `Interrupts::Masked`, it's trivially met.
+pub unsafe fn run_tasks_with_options(
+    futures: &mut [Pin<&mut dyn Future<Output = Infallible>>],
+    initial_mask: impl Into<WakeMask>,
+    interrupts: Interrupts,
+    scheduling: Scheduling,
+    idle_hook: impl FnMut(),
+) -> ! {
+    // Safety: this is safe if our own contract is upheld.
+    unsafe {
+        Executor::new(futures)
+            .initial_mask(initial_mask)
+            .interrupts(interrupts)
+    }
+    .scheduling(scheduling)
+    .idle_hook(idle_hook)
+    .run()
+}
+
+/// Extended version of `run_tasks_with_idle` whose idle hook gets an
+/// [`IdleContext`], describing when the executor next needs to wake up.
+///
+/// Like `run_tasks_with_idle`, if you'd like the processor to sleep when idle,
+/// you need to do that yourself from `idle_hook`. The context lets you choose
+/// how deeply:
+///
+/// ```ignore
+/// run_tasks_with_idle_context(&mut [a, b], ALL_TASKS, |cx| {
+///     match cx.next_deadline() {
+///         Some(d) if d > TickTime::now() + Millis(50) => deep_sleep_until(d),
+///         _ => cx.wait_for_interrupt(),
+///     }
+/// });
+/// ```
+///
+/// See [`run_tasks`] for more details.
+pub fn run_tasks_with_idle_context(
+    futures: &mut [Pin<&mut dyn Future<Output = Infallible>>],
+    initial_mask: impl Into<WakeMask>,
+    idle_hook: impl FnMut(&IdleContext),
+) -> ! {
+    Executor::new(futures)
+        .initial_mask(initial_mask)
+        .idle_hook_with_context(idle_hook)
+        .run()
+}
+
+/// What an idle hook gets to know about the executor as it goes idle. See
+/// [`run_tasks_with_idle_context`].
+#[derive(Debug)]
+pub struct IdleContext {
     interrupts: Interrupts,
-    mut idle_hook: impl FnMut(),
+}
+
+impl IdleContext {
+    /// Returns the earliest deadline any task is waiting for, or `None` if no
+    /// task is waiting on a timer. A hook that puts the processor into a deep
+    /// sleep, where the usual timer stops, should arrange to wake up by then.
+    ///
+    /// This may be in the past, if a timer expired during the last pass
+    /// through the tasks. If any interrupt executor is waiting on a timer,
+    /// this is the next tick, since those timers aren't visible from here.
+    #[cfg(feature = "time")]
+    pub fn next_deadline(&self) -> Option<TickTime> {
+        next_deadline()
+    }
+
+    /// Returns the interrupt policy the executor is running with. The idle
+    /// hook runs with interrupts masked according to this policy, so any
+    /// interrupt that wakes the processor won't be handled until the hook
+    /// returns.
+    pub fn interrupts(&self) -> Interrupts {
+        self.interrupts
+    }
+
+    /// Idles the processor until an interrupt arrives, just as `run_tasks`
+    /// does. This is for hooks that only sometimes want to do something
+    /// fancier, and works on every platform the OS does.
+    pub fn wait_for_interrupt(&self) {
+        default_idle_hook();
+    }
+}
+
+/// The executor loop behind all the `run_tasks` variants.
+///
+/// # Safety
+///
+/// This has the same contract as [`run_tasks_with_preemption_and_idle`].
+unsafe fn run_executor<I: IdleTask>(
+    futures: &mut [Pin<&mut dyn Future<Output = Infallible>>],
+    initial_mask: WakeMask,
+    interrupts: Interrupts,
+    scheduling: Scheduling,
+    mut idle_task: I,
+    mut idle_hook: impl FnMut(&IdleContext),
 ) -> ! {
     // Record the task futures for debugger access.
     {
@@ -502,39 +1113,110 @@ pub unsafe fn run_tasks_with_preemption_and_idle(
         }
     }
 
-    WAKE_BITS.store(initial_mask, Ordering::SeqCst);
+    arch::executor_started();
+
+    // The idle task, if any, goes after the others.
+    let idle_index = futures.len();
+
+    #[cfg(feature = "interrupt-executor")]
+    interrupt::check_thread_tasks(idle_index + usize::from(I::EXISTS));
+
+    #[cfg(feature = "stats")]
+    stats::note_wake_mask(&initial_mask);
+    WAKE_BITS.take();
+    WAKE_BITS.insert_all(initial_mask);
 
     // TODO make this list static for more predictable memory usage
-    #[cfg(feature = "systick")]
+    #[cfg(feature = "time")]
     create_list!(timer_list);
 
-    #[cfg(not(feature = "systick"))]
+    #[cfg(not(feature = "time"))]
+    #[allow(clippy::let_unit_value)]
     let timer_list = ();
 
+    // Tasks that have been woken but not yet polled, in priority mode.
+    let mut pending = WakeMask::new();
+    // Whether the idle task has been woken since it was last polled. It starts
+    // out woken, like everything else.
+    let mut idle_woken = true;
+
     set_timer_list(timer_list, || loop {
         interrupts.scope(|| {
-            #[cfg(feature = "systick")]
+            #[cfg(feature = "time")]
             {
                 // Scan for any expired timers.
-                with_timer_list(|tl| tl.wake_less_than(TickTime::now()));
+                wake_expired_timers(TickTime::now());
             }
 
-            // Capture and reset wake bits, then process any 1s.
-            // TODO: this loop visits every future testing for 1 bits; it would
-            // almost certainly be faster to visit the futures corresponding to
-            // 1 bits instead. I have avoided this for now because of the
-            // increased complexity.
-            let mask = WAKE_BITS.swap_polyfill(0, Ordering::SeqCst);
-            for (i, f) in futures.iter_mut().enumerate() {
-                if mask & wake_mask_for_index(i) != 0 {
-                    poll_task(i, f.as_mut());
+            match scheduling {
+                Scheduling::RoundRobin => {
+                    // Capture and reset wake bits, then process any 1s.
+                    // TODO: this loop visits every future testing for 1 bits;
+                    // it would almost certainly be faster to visit the futures
+                    // corresponding to 1 bits instead. I have avoided this for
+                    // now because of the increased complexity.
+                    let mask = WAKE_BITS.take();
+                    #[cfg(feature = "task-control")]
+                    let mask = hold_suspended(mask);
+                    if I::EXISTS {
+                        idle_woken |= mask.contains(idle_index);
+                    }
+                    for (i, f) in futures.iter_mut().enumerate() {
+                        if mask.contains(i) {
+                            poll_task(i, f.as_mut());
+                        }
+                    }
+                }
+                Scheduling::Priority => {
+                    // Fold in any new wakeups, then poll only the most
+                    // important ready task (plus anything sharing its wake
+                    // bit). Returning to the top of the loop after each poll
+                    // means we'll notice if it woke something more important
+                    // than the rest of `pending`.
+                    pending.insert_all(WAKE_BITS.take());
+                    // This also catches tasks suspended since they were woken.
+                    #[cfg(feature = "task-control")]
+                    {
+                        pending = hold_suspended(pending);
+                    }
+                    if I::EXISTS {
+                        idle_woken |= pending.contains(idle_index);
+                    }
+                    match pending.first_in(futures.len()) {
+                        Some(i) => {
+                            pending.remove(i);
+                            let stride = WAKE_WORDS * USIZE_BITS;
+                            for j in (i..futures.len()).step_by(stride) {
+                                poll_task(j, futures[j].as_mut());
+                            }
+                        }
+                        // Anything left names tasks that don't exist (e.g.
+                        // from ALL_TASKS); discard it so we can idle.
+                        None => pending = WakeMask::new(),
+                    }
                 }
             }
 
-            // If none of the futures woke each other, we're relying on an
+            // If none of the futures woke each other, it's the idle task's
+            // turn. If it doesn't want one either, we're relying on an
             // interrupt to set bits -- so we can sleep waiting for it.
-            if WAKE_BITS.load(Ordering::SeqCst) == 0 {
-                idle_hook();
+            if pending.is_empty() && WAKE_BITS.is_empty() {
+                if I::EXISTS && idle_woken {
+                    idle_woken = false;
+                    if idle_task.poll(idle_index) {
+                        // Go around again to see what it woke.
+                        return;
+                    }
+                }
+                #[cfg(feature = "trace")]
+                trace::emit(TraceEvent::IdleStart);
+                #[cfg(feature = "time")]
+                crate::time::begin_idle(next_deadline);
+                idle_hook(&IdleContext { interrupts });
+                #[cfg(feature = "time")]
+                crate::time::end_idle();
+                #[cfg(feature = "trace")]
+                trace::emit(TraceEvent::IdleEnd);
             }
 
         });
@@ -561,8 +1243,59 @@ pub unsafe fn run_tasks_with_preemption_and_idle(
 #[used]
 static mut TASK_FUTURES: Option<*mut [Pin<*mut dyn Future<Output = Infallible>>]> = None;
 
+/// Storage for a single foreign `Waker` in a `Notify`.
+#[cfg(feature = "foreign-wakers")]
+#[derive(Debug, Default)]
+struct ForeignWaker(core::cell::UnsafeCell<Option<Waker>>);
+
+// Safety: the contents are only accessed inside `arch::critical_section`.
+#[cfg(feature = "foreign-wakers")]
+unsafe impl Sync for ForeignWaker {}
+
+#[cfg(feature = "foreign-wakers")]
+impl ForeignWaker {
+    const fn new() -> Self {
+        Self(core::cell::UnsafeCell::new(None))
+    }
+
+    /// Stores `waker`, if the slot is free. Returns `true` if `waker` is now
+    /// stored, or `false` if a different waker already has the slot.
+    ///
+    /// The waker in the slot is never displaced. It would just subscribe
+    /// again when woken, displacing the newcomer in turn, and two waiters
+    /// would keep each other (and the executor) busy forever.
+    fn register(&self, waker: &Waker) -> bool {
+        // Clone outside the critical section; we don't know what it does.
+        // (The same goes for dropping it, if it turns out we can't keep it.)
+        let mut new = Some(waker.clone());
+        arch::critical_section(|| {
+            // Safety: we're in a critical section and don't let the reference
+            // escape it.
+            let slot = unsafe { &mut *self.0.get() };
+            match slot {
+                Some(w) => w.will_wake(waker),
+                None => {
+                    *slot = new.take();
+                    true
+                }
+            }
+        })
+    }
+
+    /// Wakes and forgets the stored waker, if any.
+    fn wake(&self) {
+        // Safety: we're in a critical section and don't let the reference
+        // escape it.
+        let taken = arch::critical_section(|| unsafe { (*self.0.get()).take() });
+        if let Some(w) = taken {
+            w.wake();
+        }
+    }
+}
+
 /// Constant that can be passed to `run_tasks` and `wake_tasks_by_mask` to mean
-/// "all tasks."
+/// "all tasks." This is a `usize`, but it converts into a [`WakeMask`] naming
+/// every task.
 pub const ALL_TASKS: usize = !0;
 
 /// A lightweight task notification scheme that can be used to safely route
@@ -580,7 +1313,8 @@ pub const ALL_TASKS: usize = !0;
 /// check if the desired condition has truly occurred, you'll generally want to
 /// call [`until`][Notify::until] instead of using `subscribe` directly.
 ///
-/// A `Notify` is very small (the size of a pointer), so feel free to create as
+/// A `Notify` is very small (the size of a pointer, unless you've enabled one of
+/// the `wake-bits-N` features or `foreign-wakers`), so feel free to create as
 /// many as you like.
 ///
 /// It is safe to call `notify` from an ISR, so this is the usual method by
@@ -630,8 +1364,9 @@ pub const ALL_TASKS: usize = !0;
 /// A `Notify` collects any number of task `Waker`s into a fixed-size structure
 /// without heap allocation. It does this by coalescing the `Waker`s such that
 /// they may become *imprecise*: firing the waker for task N may also spuriously
-/// wake task M, if `M % 32 == N % 32`. (Implementation-wise, this is a matter
-/// of collecting a wake bits mask from the wakers using secret knowledge.)
+/// wake task M, if they share a bit in [`WakeMask`] -- by default, if `M % 32
+/// == N % 32`. (Implementation-wise, this is a matter of collecting a wake bits
+/// mask from the wakers using secret knowledge.)
 ///
 /// While this is often not the *ideal* strategy, it has the advantage that it
 /// can be built up cheaply and torn down atomically from interrupt context.
@@ -642,16 +1377,40 @@ pub const ALL_TASKS: usize = !0;
 /// in a particular order, waking groups of tasks together, etc. -- see the
 /// [`list`][crate::list] module -- though note that `list` cannot be used from
 /// an ISR.
+///
+/// # Foreign wakers
+///
+/// The coalescing trick only works for `Waker`s made by this executor. If a
+/// `Notify` is used by a future running somewhere else -- say, under another
+/// executor nested inside a task, or inside a combinator that wraps its
+/// `Waker` -- `subscribe` notices, and handles it separately.
+///
+/// If the `foreign-wakers` feature is enabled, each `Notify` has room to
+/// remember one foreign `Waker` and wake it properly on `notify`. This costs a
+/// few words of RAM per `Notify`. The first foreign `Waker` to subscribe keeps
+/// the spot until the `Notify` is notified; any others are handled as if the
+/// feature were off.
+///
+/// Without the feature, foreign `Waker`s are woken as soon as they subscribe.
+/// This is always correct (since futures must tolerate spurious wakeups) but
+/// means the foreign future effectively polls the `Notify` rather than waiting
+/// on it. The exception is the per-branch `Waker`s used by the combinators in
+/// [`branch`][crate::branch], which are tracked by task instead, like any
+/// other `lilos` waker.
 #[derive(Debug, Default)]
 pub struct Notify {
-    mask: AtomicUsize,
+    mask: AtomicWakeMask,
+    #[cfg(feature = "foreign-wakers")]
+    foreign: ForeignWaker,
 }
 
 impl Notify {
     /// Creates a new `Notify` with no tasks waiting.
     pub const fn new() -> Self {
         Self {
-            mask: AtomicUsize::new(0),
+            mask: AtomicWakeMask::new(),
+            #[cfg(feature = "foreign-wakers")]
+            foreign: ForeignWaker::new(),
         }
     }
 
@@ -660,7 +1419,19 @@ impl Notify {
     /// This is a low-level operation. For using a `Notify` in practice, you
     /// probably want [`until`][Notify::until] instead.
     pub fn subscribe(&self, waker: &Waker) {
-        self.mask.fetch_or_polyfill(extract_mask(waker), Ordering::SeqCst);
+        match task_index_of(waker) {
+            Some(index) => self.mask.insert(index),
+            None => {
+                #[cfg(feature = "foreign-wakers")]
+                if self.foreign.register(waker) {
+                    return;
+                }
+                match crate::branch::downgrade_to_task(waker) {
+                    Some(index) => self.mask.insert(index),
+                    None => waker.wake_by_ref(),
+                }
+            }
+        }
     }
 
     /// Wakes tasks, at least all those whose waiters have been passed to
@@ -668,9 +1439,12 @@ impl Notify {
     ///
     /// As with any wake, this makes the tasks eligible for polling in the next
     /// iteration of the executor, and does not cause any code to run
-    /// immediately.
+    /// immediately. (Foreign wakers, on the other hand, run whatever code they
+    /// contain.)
     pub fn notify(&self) {
-        wake_tasks_by_mask(self.mask.swap_polyfill(0, Ordering::SeqCst))
+        wake_tasks_by_mask(self.mask.take());
+        #[cfg(feature = "foreign-wakers")]
+        self.foreign.wake();
     }
 
     /// Waits for a condition to become true, checking only when signaled by
@@ -877,38 +1651,175 @@ impl<F, T> Future for UntilRacy<'_, F>
     }
 }
 
-/// Notifies the executor that any tasks whose wake bits are set in `mask`
-/// should be polled on the next iteration.
+/// Notifies the executor that any tasks named in `mask` should be polled on the
+/// next iteration.
+///
+/// `mask` can be a [`WakeMask`], or a `usize` (see `WakeMask` for how that's
+/// interpreted).
 ///
 /// This is a very low-level operation and is rarely what you want to use. See
 /// `Notify`.
 #[inline(always)]
-pub fn wake_tasks_by_mask(mask: usize) {
-    WAKE_BITS.fetch_or_polyfill(mask, Ordering::SeqCst);
+pub fn wake_tasks_by_mask(mask: impl Into<WakeMask>) {
+    let mask = mask.into();
+    #[cfg(feature = "stats")]
+    stats::note_wake_mask(&mask);
+    #[cfg(feature = "trace")]
+    for (word, &bits) in mask.0.iter().enumerate() {
+        if bits != 0 {
+            trace::emit(TraceEvent::WakeTasks { word, bits });
+        }
+    }
+    #[cfg(feature = "interrupt-executor")]
+    let mask = interrupt::wake_mask(mask);
+    WAKE_BITS.insert_all(mask);
+    arch::wake_executor();
 }
 
 /// Notifies the executor that the task with the given `index` should be polled
 /// on the next iteration.
 ///
-/// This operation isn't precise: it may wake other tasks, but it is guaranteed
-/// to at least wake the desired task.
+/// This operation isn't necessarily precise: it may wake other tasks that
+/// share a wake bit with this one (see [`WakeMask`]), but it is guaranteed to
+/// at least wake the desired task.
+///
+/// With the `interrupt-executor` feature, this also works for tasks on an
+/// interrupt executor, which get their interrupt pended.
 #[inline(always)]
 pub fn wake_task_by_index(index: usize) {
-    wake_tasks_by_mask(wake_mask_for_index(index));
+    #[cfg(feature = "stats")]
+    stats::note_wake(index);
+    #[cfg(feature = "trace")]
+    trace::emit(TraceEvent::WakeTask(index));
+    #[cfg(feature = "interrupt-executor")]
+    if interrupt::wake_task(index) {
+        return;
+    }
+    WAKE_BITS.insert(index);
+    arch::wake_executor();
+}
+
+/// Tasks that are suspended: their wakeups are held in `LATCHED` instead of
+/// being acted on.
+#[cfg(feature = "task-control")]
+static SUSPENDED: AtomicWakeMask = AtomicWakeMask::new();
+
+/// Suspended tasks that have been woken, and will be polled when resumed.
+#[cfg(feature = "task-control")]
+static LATCHED: AtomicWakeMask = AtomicWakeMask::new();
+
+/// Suspends the task with the given `index`. It won't be polled again until
+/// it's resumed with [`resume_task`] or [`start_task`]. Wakeups that arrive in
+/// the meantime aren't lost: they're latched, and delivered on resume.
+///
+/// This is meant for mode switches -- turning off a sensor task while the
+/// system is in a low-power state, say -- rather than for synchronization
+/// between tasks, since the task doesn't get any say in the matter. It stops
+/// wherever it last returned `Pending`, holding on to whatever it was holding.
+///
+/// This can be called before the executor starts, to keep a task from running
+/// until it's started explicitly.
+///
+/// Like wakeups, suspension works on wake bits (see [`WakeMask`]), so this
+/// also suspends any task that shares a wake bit with this one.
+///
+/// This is only available with the `task-control` feature.
+#[cfg(feature = "task-control")]
+pub fn suspend_task(index: usize) {
+    SUSPENDED.insert(index);
+}
+
+/// Resumes the task with the given `index`, after [`suspend_task`]. If it was
+/// woken while suspended, it's woken now. Otherwise, it stays asleep until
+/// something wakes it, as usual.
+///
+/// This does nothing if the task isn't suspended.
+///
+/// This is only available with the `task-control` feature.
+#[cfg(feature = "task-control")]
+pub fn resume_task(index: usize) {
+    SUSPENDED.remove(index);
+    if LATCHED.remove(index) {
+        wake_task_by_index(index);
+    }
+}
+
+/// Stops the task with the given `index`: it's suspended as by
+/// [`suspend_task`], and any wakeup it's already received is thrown away, so
+/// it won't be polled until it's started again with [`start_task`].
+///
+/// Note that the task's future isn't dropped or reset. When started again, it
+/// picks up from where it left off.
+///
+/// This is only available with the `task-control` feature.
+#[cfg(feature = "task-control")]
+pub fn stop_task(index: usize) {
+    SUSPENDED.insert(index);
+    LATCHED.remove(index);
+}
+
+/// Starts the task with the given `index`: if it's suspended or stopped, it's
+/// resumed, and either way, it's woken. This is how to start a task that was
+/// left out of the initial mask passed to [`run_tasks`], or that was stopped
+/// with [`stop_task`].
+///
+/// This is only available with the `task-control` feature.
+#[cfg(feature = "task-control")]
+pub fn start_task(index: usize) {
+    SUSPENDED.remove(index);
+    LATCHED.remove(index);
+    wake_task_by_index(index);
+}
+
+/// Checks whether the task with the given `index` is suspended (or stopped),
+/// or shares a wake bit with one that is.
+///
+/// This is only available with the `task-control` feature.
+#[cfg(feature = "task-control")]
+pub fn is_task_suspended(index: usize) -> bool {
+    SUSPENDED.load().contains(index)
+}
+
+/// Called by executors on the set of tasks they're about to poll. Latches the
+/// wakeups of any tasks that are suspended, and returns the rest.
+#[cfg(feature = "task-control")]
+fn hold_suspended(mask: WakeMask) -> WakeMask {
+    let held = mask.intersection(SUSPENDED.load());
+    if held.is_empty() {
+        return mask;
+    }
+    LATCHED.insert_all(held);
+    // A task resumed after we checked, but before we latched its wakeup, would
+    // miss it. Poll any such tasks now instead. If `resume_task` got to the
+    // latch first, this gives them a spurious wakeup, which is fine.
+    let resumed = held.difference(SUSPENDED.load());
+    LATCHED.remove_all(resumed);
+    mask.difference(held.difference(resumed))
 }
 
 /// Tracks the timer list currently in scope.
-#[cfg(feature = "systick")]
+#[cfg(feature = "time")]
 static TIMER_LIST: AtomicPtr<List<TickTime>> =
     AtomicPtr::new(core::ptr::null_mut());
 
 /// Panics if called from an interrupt service routine (ISR). This is used to
 /// prevent OS features that are unavailable to ISRs from being used in ISRs.
-#[cfg(feature = "systick")]
+///
+/// The handler running an [`InterruptExecutor`] doesn't count, while it's
+/// polling tasks.
+#[cfg(any(
+    feature = "time",
+    feature = "stats",
+    feature = "trace",
+    feature = "interrupt-executor",
+))]
 fn assert_not_in_isr() {
-    let psr_value = cortex_m::register::apsr::read().bits();
-    // Bottom 9 bits are the exception number, which are 0 in Thread mode.
-    if psr_value & 0x1FF != 0 {
+    #[cfg(feature = "interrupt-executor")]
+    let in_isr = arch::interrupt_context() != interrupt::current_context();
+    #[cfg(not(feature = "interrupt-executor"))]
+    let in_isr = arch::in_isr();
+
+    if in_isr {
         panic!();
     }
 }
@@ -916,7 +1827,7 @@ fn assert_not_in_isr() {
 /// Sets the timer list for the duration of `body`.
 ///
 /// This doesn't nest, and will assert if you try.
-#[cfg(feature = "systick")]
+#[cfg(feature = "time")]
 #[inline(always)]
 fn set_timer_list<R>(
     list: Pin<&mut List<TickTime>>,
@@ -950,7 +1861,7 @@ fn set_timer_list<R>(
 }
 
 /// No-op definition of `set_timer_list` if the OS has no actual timer list.
-#[cfg(not(feature = "systick"))]
+#[cfg(not(feature = "time"))]
 #[inline(always)]
 fn set_timer_list<X, R>(
     _list: X,
@@ -968,7 +1879,7 @@ fn set_timer_list<X, R>(
 /// - Must not be called from an interrupt.
 /// - Must only be called with a timer list available, which is to say, from
 ///   within a task.
-#[cfg(feature = "systick")]
+#[cfg(feature = "time")]
 pub(crate) fn with_timer_list<R>(body: impl FnOnce(Pin<&List<TickTime>>) -> R) -> R {
     // Prevent this from being used from interrupt context.
     assert_not_in_isr();
@@ -991,6 +1902,36 @@ pub(crate) fn with_timer_list<R>(body: impl FnOnce(Pin<&List<TickTime>>) -> R) -
     body(list_ref)
 }
 
+/// Returns the earliest time at which a timer will need to be checked, or
+/// `None` if nothing is waiting on a timer.
+///
+/// This has the same preconditions as `with_timer_list`.
+#[cfg(feature = "time")]
+fn next_deadline() -> Option<TickTime> {
+    // Interrupt executors keep their own timer lists, which we can't see from
+    // here. If any of them are waiting, keep ticking so they get checked.
+    #[cfg(feature = "interrupt-executor")]
+    if interrupt::timers_pending() {
+        let next_tick = u64::from(TickTime::now()) + 1;
+        return Some(TickTime::from_ticks_since_boot(next_tick));
+    }
+    with_timer_list(|tl| tl.first())
+}
+
+/// Wakes any tasks waiting on timers that expire at or before `now`.
+///
+/// This has the same preconditions as `with_timer_list`.
+#[cfg(feature = "time")]
+pub(crate) fn wake_expired_timers(now: TickTime) {
+    with_timer_list(|tl| {
+        #[cfg(feature = "trace")]
+        if tl.first().map_or(false, |t| t <= now) {
+            trace::emit(TraceEvent::TimersFired);
+        }
+        tl.wake_less_than(now)
+    })
+}
+
 /// Returns a future that will be pending exactly once before resolving.
 ///
 /// This can be used to give up CPU to any other tasks that are currently ready
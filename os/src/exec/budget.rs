@@ -0,0 +1,93 @@
+//! Long-poll detection, enabled by the `poll-budget` feature.
+//!
+//! A task that goes a long time without returning `Pending` holds up every
+//! other task -- and, with interrupts masked, every ISR. This is easy to do by
+//! accident (a loop that forgot to `yield_cpu`, a driver that busy-waits) and
+//! hard to spot, since everything still _works,_ just late.
+//!
+//! To catch it, give the executor a budget with [`set_poll_budget`] (or
+//! [`Executor::poll_budget`][super::Executor::poll_budget]), along with a hook
+//! to call when a task exceeds it. After every poll, the executor compares the
+//! time the poll took against the budget, and if it's over, calls the hook
+//! with a [`PollOverrun`] saying which task it was and how long it took. The
+//! hook can log it, count it, or panic, to make overruns fail your tests.
+//!
+//! Times are measured with the same counter as task statistics; see the
+//! `stats` module for its units, and its caveats.
+//!
+//! The check happens when the poll finishes, so a task that never returns at
+//! all won't be reported. That's a job for a hardware watchdog.
+
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+use super::timestamp;
+
+/// Details of a task poll that took longer than the budget, as passed to the
+/// hook given to [`set_poll_budget`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PollOverrun {
+    /// Index of the task that overran. Tasks on an `InterruptExecutor` are
+    /// reported by their index in the task numbering shared by all executors,
+    /// the same index their `Waker`s use.
+    pub task: usize,
+    /// How long the poll took.
+    pub elapsed: u32,
+    /// The budget in effect at the time.
+    pub budget: u32,
+}
+
+impl PollOverrun {
+    /// Returns how far over budget the poll went.
+    pub fn excess(&self) -> u32 {
+        self.elapsed - self.budget
+    }
+}
+
+/// Longest poll that isn't reported. Starts out at a value no poll can exceed.
+static BUDGET: AtomicU32 = AtomicU32::new(u32::MAX);
+/// The hook, as a `fn(PollOverrun)`. Always written before `BUDGET`, so it's
+/// valid by the time any poll can be over budget.
+static HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Starts checking task polls against `budget`, calling `hook` from the
+/// executor after any poll that takes longer.
+///
+/// This can be called again to change the budget or the hook. To stop
+/// checking, use [`clear_poll_budget`].
+///
+/// The hook runs in the same context as the task that overran: in thread mode
+/// with interrupts masked, usually, or inside the ISR for tasks on an
+/// interrupt executor.
+pub fn set_poll_budget(budget: u32, hook: fn(PollOverrun)) {
+    HOOK.store(hook as *mut (), Ordering::Release);
+    BUDGET.store(budget, Ordering::Release);
+}
+
+/// Stops checking task polls against a budget, until the next call to
+/// [`set_poll_budget`].
+pub fn clear_poll_budget() {
+    BUDGET.store(u32::MAX, Ordering::Release);
+}
+
+/// Called just before polling a task.
+#[inline(always)]
+pub(super) fn poll_started() -> u32 {
+    timestamp()
+}
+
+/// Called just after polling the task with the given `index`.
+#[inline(always)]
+pub(super) fn poll_finished(index: usize, start: u32) {
+    let elapsed = timestamp().wrapping_sub(start);
+    let budget = BUDGET.load(Ordering::Acquire);
+    if elapsed > budget {
+        // Safety: HOOK only ever holds a `fn(PollOverrun)`, and is written
+        // before any budget a poll could exceed.
+        let hook = unsafe {
+            core::mem::transmute::<*mut (), fn(PollOverrun)>(
+                HOOK.load(Ordering::Acquire),
+            )
+        };
+        hook(PollOverrun { task: index, elapsed, budget });
+    }
+}
@@ -0,0 +1,371 @@
+//! The `Executor` builder, which collects the executor's options before
+//! starting it.
+
+use core::convert::Infallible;
+use core::future::Future;
+use core::pin::Pin;
+
+use super::{
+    default_idle_hook, poll_task, run_executor, IdleContext, Interrupts,
+    Scheduling, WakeMask, ALL_TASKS,
+};
+
+/// Configures and starts the executor. This is the most general way of doing
+/// so: the `run_tasks` family of functions are all shorthand for particular
+/// configurations of an `Executor`.
+///
+/// Start with [`Executor::new`], giving it the tasks, then set any options you
+/// need, and finish with [`run`][Executor::run]:
+///
+/// ```ignore
+/// static STATS: TaskStatsTable<3> = TaskStatsTable::new();
+///
+/// Executor::new(&mut [a, b, c])
+///     .initial_mask(WakeMask::new().with_task(0).with_task(1))
+///     .scheduling(Scheduling::Priority)
+///     .idle_task(background)
+///     .task_stats(&STATS)
+///     .run()
+/// ```
+///
+/// Any option that isn't set has the same default as in [`run_tasks`]: every
+/// task is started, interrupts are masked while tasks run, tasks are polled in
+/// round-robin order, and the processor sleeps when nothing is ready.
+///
+/// [`run_tasks`]: super::run_tasks
+#[must_use = "the executor doesn't start until you call `run`"]
+pub struct Executor<'t, 'f, I = (), H = DefaultIdleHook> {
+    tasks: &'t mut [Pin<&'f mut dyn Future<Output = Infallible>>],
+    initial_mask: WakeMask,
+    interrupts: Interrupts,
+    scheduling: Scheduling,
+    idle_task: I,
+    idle_hook: H,
+    #[cfg(feature = "stats")]
+    stats: Option<&'static [super::stats::Slot]>,
+    #[cfg(feature = "trace")]
+    trace_sink: Option<&'static dyn super::TraceSink>,
+    #[cfg(feature = "poll-budget")]
+    poll_budget: Option<(u32, fn(super::PollOverrun))>,
+    #[cfg(feature = "time-slice")]
+    time_slice: Option<u32>,
+    #[cfg(feature = "task-isolation")]
+    task_isolation: Option<&'static dyn crate::isolation::TaskIsolation>,
+}
+
+impl<I, H> core::fmt::Debug for Executor<'_, '_, I, H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Executor")
+            .field("tasks", &self.tasks.len())
+            .field("initial_mask", &self.initial_mask)
+            .field("interrupts", &self.interrupts)
+            .field("scheduling", &self.scheduling)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'t, 'f> Executor<'t, 'f> {
+    /// Starts configuring an executor that will run `tasks`.
+    #[inline(always)]
+    pub fn new(tasks: &'t mut [Pin<&'f mut dyn Future<Output = Infallible>>]) -> Self {
+        Self {
+            tasks,
+            initial_mask: ALL_TASKS.into(),
+            interrupts: Interrupts::Masked,
+            scheduling: Scheduling::RoundRobin,
+            idle_task: (),
+            idle_hook: DefaultIdleHook,
+            #[cfg(feature = "stats")]
+            stats: None,
+            #[cfg(feature = "trace")]
+            trace_sink: None,
+            #[cfg(feature = "poll-budget")]
+            poll_budget: None,
+            #[cfg(feature = "time-slice")]
+            time_slice: None,
+            #[cfg(feature = "task-isolation")]
+            task_isolation: None,
+        }
+    }
+}
+
+impl<'t, 'f, I, H> Executor<'t, 'f, I, H> {
+    /// Chooses which tasks are polled when the executor starts. The rest wait
+    /// until something wakes them. By default, all tasks are polled.
+    ///
+    /// `mask` can be a [`WakeMask`], or a `usize` (see `WakeMask` for how
+    /// that's interpreted).
+    #[inline(always)]
+    pub fn initial_mask(self, mask: impl Into<WakeMask>) -> Self {
+        Self {
+            initial_mask: mask.into(),
+            ..self
+        }
+    }
+
+    /// Sets the interrupt policy used while running task code, as in
+    /// [`run_tasks_with_preemption`][super::run_tasks_with_preemption]. By
+    /// default, interrupts are masked.
+    ///
+    /// # Safety
+    ///
+    /// This has the same contract as `run_tasks_with_preemption`; if you're
+    /// passing `Interrupts::Masked`, it's trivially met.
+    #[inline(always)]
+    pub unsafe fn interrupts(self, interrupts: Interrupts) -> Self {
+        Self { interrupts, ..self }
+    }
+
+    /// Sets the order in which ready tasks are polled. See [`Scheduling`]. By
+    /// default, it's round-robin.
+    #[inline(always)]
+    pub fn scheduling(self, scheduling: Scheduling) -> Self {
+        Self { scheduling, ..self }
+    }
+
+    /// Replaces the default idle behavior (sleeping until the next interrupt)
+    /// with `hook`, as in [`run_tasks_with_idle`][super::run_tasks_with_idle].
+    #[inline(always)]
+    pub fn idle_hook(
+        self,
+        mut hook: impl FnMut(),
+    ) -> Executor<'t, 'f, I, impl FnMut(&IdleContext)> {
+        self.idle_hook_with_context(move |_: &IdleContext| hook())
+    }
+
+    /// Replaces the default idle behavior with `hook`, which gets an
+    /// [`IdleContext`], as in
+    /// [`run_tasks_with_idle_context`][super::run_tasks_with_idle_context].
+    #[inline(always)]
+    pub fn idle_hook_with_context<G: FnMut(&IdleContext)>(
+        self,
+        hook: G,
+    ) -> Executor<'t, 'f, I, G> {
+        Executor {
+            tasks: self.tasks,
+            initial_mask: self.initial_mask,
+            interrupts: self.interrupts,
+            scheduling: self.scheduling,
+            idle_task: self.idle_task,
+            idle_hook: hook,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+            #[cfg(feature = "trace")]
+            trace_sink: self.trace_sink,
+            #[cfg(feature = "poll-budget")]
+            poll_budget: self.poll_budget,
+            #[cfg(feature = "time-slice")]
+            time_slice: self.time_slice,
+            #[cfg(feature = "task-isolation")]
+            task_isolation: self.task_isolation,
+        }
+    }
+
+    /// Adds an idle task, which is only polled when no other task is ready,
+    /// as in [`run_tasks_with_idle_task`][super::run_tasks_with_idle_task].
+    #[inline(always)]
+    pub fn idle_task<'g>(
+        self,
+        task: Pin<&'g mut dyn Future<Output = Infallible>>,
+    ) -> Executor<'t, 'f, Pin<&'g mut dyn Future<Output = Infallible>>, H> {
+        Executor {
+            tasks: self.tasks,
+            initial_mask: self.initial_mask,
+            interrupts: self.interrupts,
+            scheduling: self.scheduling,
+            idle_task: task,
+            idle_hook: self.idle_hook,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+            #[cfg(feature = "trace")]
+            trace_sink: self.trace_sink,
+            #[cfg(feature = "poll-budget")]
+            poll_budget: self.poll_budget,
+            #[cfg(feature = "time-slice")]
+            time_slice: self.time_slice,
+            #[cfg(feature = "task-isolation")]
+            task_isolation: self.task_isolation,
+        }
+    }
+
+    /// Records per-task statistics in `table`, as if by
+    /// [`enable_task_stats`][super::enable_task_stats] when the executor
+    /// starts.
+    ///
+    /// This is only available with the `stats` feature.
+    #[cfg(feature = "stats")]
+    #[inline(always)]
+    pub fn task_stats<const N: usize>(
+        self,
+        table: &'static super::TaskStatsTable<N>,
+    ) -> Self {
+        Self {
+            stats: Some(table.slots()),
+            ..self
+        }
+    }
+
+    /// Reports scheduling events to `sink`, as if by
+    /// [`set_trace_sink`][super::set_trace_sink] when the executor starts.
+    ///
+    /// This is only available with the `trace` feature.
+    #[cfg(feature = "trace")]
+    #[inline(always)]
+    pub fn trace_sink(self, sink: &'static dyn super::TraceSink) -> Self {
+        Self {
+            trace_sink: Some(sink),
+            ..self
+        }
+    }
+
+    /// Calls `hook` whenever a task poll takes longer than `budget`, as if by
+    /// [`set_poll_budget`][super::set_poll_budget] when the executor starts.
+    ///
+    /// This is only available with the `poll-budget` feature.
+    #[cfg(feature = "poll-budget")]
+    #[inline(always)]
+    pub fn poll_budget(self, budget: u32, hook: fn(super::PollOverrun)) -> Self {
+        Self {
+            poll_budget: Some((budget, hook)),
+            ..self
+        }
+    }
+
+    /// Sets how long a task can run before [`maybe_yield`][super::maybe_yield]
+    /// yields, as if by [`set_time_slice`][super::set_time_slice] when the
+    /// executor starts.
+    ///
+    /// This is only available with the `time-slice` feature.
+    #[cfg(feature = "time-slice")]
+    #[inline(always)]
+    pub fn time_slice(self, length: u32) -> Self {
+        Self {
+            time_slice: Some(length),
+            ..self
+        }
+    }
+
+    /// Applies `isolation` around every task poll, as if by
+    /// [`set_task_isolation`][crate::isolation::set_task_isolation] when the
+    /// executor starts.
+    ///
+    /// This is only available with the `task-isolation` feature.
+    #[cfg(feature = "task-isolation")]
+    #[inline(always)]
+    pub fn task_isolation(
+        self,
+        isolation: &'static dyn crate::isolation::TaskIsolation,
+    ) -> Self {
+        Self {
+            task_isolation: Some(isolation),
+            ..self
+        }
+    }
+}
+
+impl<I, H> Executor<'_, '_, I, H>
+where
+    I: IdleTask,
+    H: IdleHook,
+{
+    /// Starts the executor, running the tasks forever.
+    ///
+    /// # Panics
+    ///
+    /// If statistics or a trace sink were configured here, and have already
+    /// been enabled some other way.
+    #[inline(always)]
+    pub fn run(self) -> ! {
+        #[cfg(feature = "stats")]
+        if let Some(slots) = self.stats {
+            super::stats::enable_slots(slots);
+        }
+        #[cfg(feature = "trace")]
+        if let Some(sink) = self.trace_sink {
+            super::set_trace_sink(sink);
+        }
+        #[cfg(feature = "poll-budget")]
+        if let Some((budget, hook)) = self.poll_budget {
+            super::set_poll_budget(budget, hook);
+        }
+        #[cfg(feature = "time-slice")]
+        if let Some(length) = self.time_slice {
+            super::set_time_slice(length);
+        }
+        #[cfg(feature = "task-isolation")]
+        if let Some(isolation) = self.task_isolation {
+            crate::isolation::set_task_isolation(isolation);
+        }
+        let mut idle_hook = self.idle_hook;
+        // Safety: the only unsafe option is the interrupt policy, and whoever
+        // set it took responsibility for it.
+        unsafe {
+            run_executor(
+                self.tasks,
+                self.initial_mask,
+                self.interrupts,
+                self.scheduling,
+                self.idle_task,
+                |cx| idle_hook.idle(cx),
+            )
+        }
+    }
+}
+
+/// The optional idle task given to `run_executor`. This is a trait, rather
+/// than an `Option`, so that executors without an idle task don't pay for the
+/// code to handle one.
+///
+/// This is public so that it can appear in `Executor`'s bounds, but it's in a
+/// private module, so nobody else can name it or implement it.
+pub trait IdleTask {
+    /// Whether there might be an idle task.
+    const EXISTS: bool;
+
+    /// Polls the idle task, which has task index `index`, returning `false`
+    /// if there turned out not to be one.
+    fn poll(&mut self, index: usize) -> bool;
+}
+
+/// No idle task.
+impl IdleTask for () {
+    const EXISTS: bool = false;
+
+    fn poll(&mut self, _index: usize) -> bool {
+        false
+    }
+}
+
+impl IdleTask for Pin<&mut dyn Future<Output = Infallible>> {
+    const EXISTS: bool = true;
+
+    fn poll(&mut self, index: usize) -> bool {
+        poll_task(index, self.as_mut());
+        true
+    }
+}
+
+/// The idle hook an `Executor` uses unless it's given another one: sleep until
+/// the next interrupt. This is a type of its own, rather than a function
+/// pointer, so that executors using it don't pay for an indirect call.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DefaultIdleHook;
+
+/// An idle hook given to `Executor`: either the default, or a closure. Like
+/// `IdleTask`, nobody outside this module can name it.
+pub trait IdleHook {
+    /// Called when no tasks are ready to run.
+    fn idle(&mut self, cx: &IdleContext);
+}
+
+impl IdleHook for DefaultIdleHook {
+    fn idle(&mut self, _cx: &IdleContext) {
+        default_idle_hook();
+    }
+}
+
+impl<F: FnMut(&IdleContext)> IdleHook for F {
+    fn idle(&mut self, cx: &IdleContext) {
+        self(cx)
+    }
+}
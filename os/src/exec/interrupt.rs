@@ -0,0 +1,403 @@
+//! Executors that run inside interrupt handlers, enabled by the
+//! `interrupt-executor` feature.
+//!
+//! The normal executor started by `run_tasks` runs in Thread mode, and its
+//! tasks take turns: a task that takes a long time to poll holds up every
+//! other task, no matter how urgent. An [`InterruptExecutor`] runs a second
+//! set of tasks from inside an interrupt handler -- usually PendSV, or an NVIC
+//! vector that your SoC doesn't otherwise use -- which the hardware can run in
+//! the middle of a Thread-mode poll. Tasks on the interrupt executor can thus
+//! preempt tasks on the Thread-mode executor, while still using `Notify`,
+//! `spsc` queues, and the `time` module to wait for things.
+//!
+//! # Task numbering
+//!
+//! All executors share one space of task indices, so that the `Waker`s,
+//! `Notify`s, and `wake_task*` functions in the `exec` module don't need to
+//! know which executor a task belongs to. The Thread-mode executor's tasks are
+//! numbered from 0, as usual. An interrupt executor is given the index of its
+//! first task when it's created, and numbers its tasks consecutively from
+//! there. Its tasks must come after all of the Thread-mode tasks, and must fit
+//! in the wake bitmap (see [`WakeMask`]) without aliasing, so that they get
+//! exact wakeups.
+//!
+//! Waking a task that belongs to an interrupt executor pends its interrupt,
+//! using a function you provide. The interrupt handler then calls
+//! [`InterruptExecutor::run`], which polls the executor's ready tasks until
+//! none are left, and returns.
+//!
+//! # Example
+//!
+//! ```ignore
+//! // Our Thread-mode executor will have four tasks, so the urgent tasks start
+//! // at index 4.
+//! static URGENT: InterruptExecutor =
+//!     InterruptExecutor::new(4, cortex_m::peripheral::SCB::set_pendsv);
+//!
+//! #[exception]
+//! fn PendSV() {
+//!     URGENT.run();
+//! }
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!     let mut cp = cortex_m::Peripherals::take().unwrap();
+//!     // Let PendSV preempt task code running with interrupts filtered at
+//!     // 0x80.
+//!     unsafe {
+//!         cp.SCB.set_priority(SystemHandler::PendSV, 0x40);
+//!     }
+//!
+//!     let motor = pin!(motor_control());
+//!     // ... other tasks ...
+//!
+//!     // Safety: our tasks only share Notify and spsc queues between
+//!     // executors, and our ISRs are okay with being preempted.
+//!     unsafe {
+//!         URGENT.start(&mut [motor], ALL_TASKS, || {
+//!             exec::run_tasks_with_preemption(
+//!                 &mut [ui, logger, comms, housekeeping],
+//!                 ALL_TASKS,
+//!                 Interrupts::Filtered(0x80),
+//!             )
+//!         })
+//!     }
+//! }
+//! ```
+//!
+//! # Priorities
+//!
+//! An interrupt executor can only preempt the tasks that its interrupt can
+//! preempt. If the Thread-mode executor masks all interrupts while polling
+//! (`Interrupts::Masked`, as `run_tasks` does), the interrupt executor still
+//! works, but its tasks only run in between polls of Thread-mode tasks. To let
+//! them cut into a poll, start the Thread-mode executor with
+//! `Interrupts::Filtered` and give the interrupt a priority above the filter.
+//!
+//! You can create more than one interrupt executor, on different interrupts at
+//! different priorities, to get more levels of preemption. While an interrupt
+//! executor is running, it holds off any interrupts at its own priority or
+//! lower, so keep its tasks brief.
+//!
+//! # Timers
+//!
+//! Each interrupt executor has its own timer list, so its tasks can sleep and
+//! use timeouts. While any of its tasks is waiting on a timer, the executor's
+//! interrupt is pended on every tick so that it can check for expired timers.
+//!
+//! # Hosted builds
+//!
+//! With the `hosted` feature, there are no interrupts to pend, so `pend` can
+//! simply call `run`. The executor's tasks then run on the spot, as if their
+//! interrupt had preempted whatever woke them. This only works if they're
+//! woken from the executor thread, since they'd otherwise run alongside it
+//! rather than preempting it.
+
+use core::convert::Infallible;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use crate::arch;
+use crate::atomic::AtomicExt;
+use crate::cheap_assert;
+use super::{poll_task, AtomicWakeMask, WakeMask, WAKE_BITS_COUNT};
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "time")] {
+        use crate::list::List;
+        use crate::time::TickTime;
+        use super::{wake_expired_timers, TIMER_LIST};
+    }
+}
+
+/// Task type as stored by an `InterruptExecutor`. The lifetimes are a lie;
+/// see `InterruptExecutor::start` for why that's okay.
+type Task = Pin<&'static mut dyn Future<Output = Infallible>>;
+
+/// An executor that runs its tasks from an interrupt handler, so that they can
+/// preempt tasks on the main, Thread-mode executor. See the [module
+/// docs](self) for the big picture.
+///
+/// This is intended to be stored in a `static`, so that your interrupt handler
+/// can find it.
+#[derive(Debug)]
+pub struct InterruptExecutor {
+    /// Index of our first task.
+    first_task: usize,
+    /// Function that pends our interrupt.
+    pend: fn(),
+    /// Number of tasks; zero until we're started.
+    task_count: AtomicUsize,
+    /// Our first task (a `*mut Task`, which can't be named in a `const fn`);
+    /// the rest follow it. Written once, by `start`, before `started` is set.
+    tasks: AtomicPtr<()>,
+    /// Set once `start` has finished setting things up.
+    started: AtomicBool,
+    /// Set while some call to `run` is polling our tasks.
+    running: AtomicBool,
+    /// Wake bits for our tasks, using their global indices.
+    wake_bits: AtomicWakeMask,
+    /// Our timer list. Written once, by `start`, before `started` is set.
+    #[cfg(feature = "time")]
+    timer_list: AtomicPtr<List<TickTime>>,
+    /// Set if the timer list was non-empty the last time we ran.
+    #[cfg(feature = "time")]
+    has_timers: AtomicBool,
+    /// Next executor in the list of started executors.
+    next: AtomicPtr<InterruptExecutor>,
+}
+
+/// Head of the list of started interrupt executors.
+static EXECUTORS: AtomicPtr<InterruptExecutor> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// `arch::interrupt_context` value of the innermost executor that's currently
+/// polling tasks. This is 0, Thread mode, unless an interrupt executor is
+/// running.
+static EXECUTOR_CONTEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns an iterator over the started interrupt executors.
+fn executors() -> impl Iterator<Item = &'static InterruptExecutor> {
+    let first = EXECUTORS.load(Ordering::Acquire);
+    // Safety: only `&'static InterruptExecutor`s ever get put in the list.
+    core::iter::successors(unsafe { first.as_ref() }, |e| unsafe {
+        e.next.load(Ordering::Acquire).as_ref()
+    })
+}
+
+impl InterruptExecutor {
+    /// Creates an interrupt executor whose tasks will be numbered starting at
+    /// `first_task`, and which wakes up by calling `pend`.
+    ///
+    /// `pend` should pend the interrupt whose handler calls
+    /// [`run`][InterruptExecutor::run] -- for PendSV, that's
+    /// `cortex_m::peripheral::SCB::set_pendsv`. It can be called from any
+    /// context, including other ISRs.
+    pub const fn new(first_task: usize, pend: fn()) -> Self {
+        Self {
+            first_task,
+            pend,
+            task_count: AtomicUsize::new(0),
+            tasks: AtomicPtr::new(core::ptr::null_mut()),
+            started: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+            wake_bits: AtomicWakeMask::new(),
+            #[cfg(feature = "time")]
+            timer_list: AtomicPtr::new(core::ptr::null_mut()),
+            #[cfg(feature = "time")]
+            has_timers: AtomicBool::new(false),
+            next: AtomicPtr::new(core::ptr::null_mut()),
+        }
+    }
+
+    /// Hands `futures` to this executor as its tasks, and then calls `body`,
+    /// which is expected to start the Thread-mode executor.
+    ///
+    /// `futures[0]` becomes task number `first_task` (as passed to
+    /// [`new`][InterruptExecutor::new]), `futures[1]` becomes `first_task +
+    /// 1`, and so on. The tasks named in `initial_mask`, which uses those
+    /// same numbers, are polled as soon as the interrupt gets a chance to run.
+    ///
+    /// Because `body` never returns, the futures stay put for as long as the
+    /// executor might poll them, which is what lets them live on the stack.
+    ///
+    /// # Panics
+    ///
+    /// If this executor has already been started, if it's called from an ISR,
+    /// or if the tasks don't fit in the wake bitmap.
+    ///
+    /// # Safety
+    ///
+    /// Once this is called, this executor's tasks can preempt tasks on the
+    /// Thread-mode executor (and, depending on priorities, some of your ISRs).
+    /// Anything they share needs to be safe under preemption: `Notify`, `spsc`
+    /// queues, atomics, and data protected by critical sections are fine. Most
+    /// other OS facilities, such as `Mutex` and `list`, assume that tasks
+    /// never preempt each other, and must not be shared across executors.
+    pub unsafe fn start(
+        &'static self,
+        futures: &mut [Pin<&mut dyn Future<Output = Infallible>>],
+        initial_mask: impl Into<WakeMask>,
+        body: impl FnOnce() -> Infallible,
+    ) -> ! {
+        super::assert_not_in_isr();
+        cheap_assert!(!self.started.load(Ordering::Relaxed));
+        cheap_assert!(self.first_task + futures.len() <= WAKE_BITS_COUNT);
+
+        #[cfg(feature = "time")]
+        {
+            create_list!(timer_list);
+            // Safety: we never use `timer_list` again, and since we don't
+            // return, it outlives every use of the pointer.
+            self.timer_list.store(
+                unsafe { Pin::get_unchecked_mut(timer_list) },
+                Ordering::Relaxed,
+            );
+        }
+
+        // Stash the futures as a raw pointer, dropping their lifetimes, which
+        // we can do because we never return.
+        self.tasks.store(futures.as_mut_ptr().cast(), Ordering::Relaxed);
+        self.task_count.store(futures.len(), Ordering::Relaxed);
+
+        let initial_mask = initial_mask.into();
+        for i in self.task_range() {
+            if initial_mask.contains(i) {
+                self.wake_bits.insert(i);
+            }
+        }
+
+        // Publish.
+        self.next.store(EXECUTORS.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.started.store(true, Ordering::Release);
+        let this: *const Self = self;
+        EXECUTORS.store(this.cast_mut(), Ordering::Release);
+
+        if !self.wake_bits.is_empty() {
+            (self.pend)();
+        }
+
+        // `body` can't return, since it can't produce an `Infallible`.
+        #[allow(unreachable_code)]
+        match body() {};
+    }
+
+    /// Polls this executor's tasks until none of them are ready, and then
+    /// returns. This is meant to be called from the executor's interrupt
+    /// handler.
+    ///
+    /// This does nothing if the executor hasn't been started, or if it's
+    /// already running further up the stack.
+    pub fn run(&self) {
+        if !self.started.load(Ordering::Acquire) {
+            return;
+        }
+
+        loop {
+            if self.running.swap_polyfill(true, Ordering::Acquire) {
+                // Whoever's running will pick up any new wakeups.
+                return;
+            }
+
+            let context = EXECUTOR_CONTEXT.load(Ordering::Relaxed);
+            EXECUTOR_CONTEXT.store(arch::interrupt_context(), Ordering::Relaxed);
+            #[cfg(feature = "time")]
+            let thread_list = TIMER_LIST.load(Ordering::Acquire);
+            #[cfg(feature = "time")]
+            TIMER_LIST.store(self.timer_list.load(Ordering::Relaxed), Ordering::Release);
+
+            // Safety: `tasks` and `task_count` were written before `started`
+            // was set, and describe futures that stay put because `start`
+            // never returns. The `running` flag gives us exclusive access.
+            let tasks = unsafe {
+                core::slice::from_raw_parts_mut(
+                    self.tasks.load(Ordering::Relaxed).cast::<Task>(),
+                    self.task_count.load(Ordering::Relaxed),
+                )
+            };
+            loop {
+                #[cfg(feature = "time")]
+                wake_expired_timers(TickTime::now());
+
+                let mask = self.wake_bits.take();
+                if mask.is_empty() {
+                    break;
+                }
+                #[cfg(feature = "task-control")]
+                let mask = super::hold_suspended(mask);
+                for (i, f) in self.task_range().zip(tasks.iter_mut()) {
+                    if mask.contains(i) {
+                        poll_task(i, f.as_mut());
+                    }
+                }
+            }
+
+            #[cfg(feature = "time")]
+            {
+                let waiting = super::with_timer_list(|tl| tl.first().is_some());
+                self.has_timers.store(waiting, Ordering::Relaxed);
+                TIMER_LIST.store(thread_list, Ordering::Release);
+            }
+            EXECUTOR_CONTEXT.store(context, Ordering::Relaxed);
+            self.running.store(false, Ordering::Release);
+
+            // Catch any wakeup that arrived just as we were finishing, and
+            // was turned away by the `running` flag.
+            if self.wake_bits.is_empty() {
+                return;
+            }
+        }
+    }
+
+    /// Returns the global indices of this executor's tasks.
+    fn task_range(&self) -> core::ops::Range<usize> {
+        let count = self.task_count.load(Ordering::Relaxed);
+        self.first_task..self.first_task + count
+    }
+}
+
+/// Returns the `arch::interrupt_context` of the innermost running executor,
+/// which is the only interrupt context where task-only operations are allowed.
+pub(super) fn current_context() -> usize {
+    EXECUTOR_CONTEXT.load(Ordering::Relaxed)
+}
+
+/// Checks that the Thread-mode executor's tasks, numbered from 0 to `count -
+/// 1`, don't collide with any interrupt executor's.
+pub(super) fn check_thread_tasks(count: usize) {
+    for e in executors() {
+        cheap_assert!(count <= e.first_task);
+    }
+}
+
+/// Routes a wakeup for the task with the given `index` to the interrupt
+/// executor that owns it, if any. Returns `true` if one did.
+#[inline(always)]
+pub(super) fn wake_task(index: usize) -> bool {
+    for e in executors() {
+        if e.task_range().contains(&index) {
+            e.wake_bits.insert(index);
+            (e.pend)();
+            return true;
+        }
+    }
+    false
+}
+
+/// Routes wakeups for any tasks in `mask` that belong to interrupt executors,
+/// and returns the rest.
+pub(super) fn wake_mask(mut mask: WakeMask) -> WakeMask {
+    for e in executors() {
+        let mut woke = false;
+        for i in e.task_range() {
+            if mask.contains(i) {
+                mask.remove(i);
+                e.wake_bits.insert(i);
+                woke = true;
+            }
+        }
+        if woke {
+            (e.pend)();
+        }
+    }
+    mask
+}
+
+/// Checks whether any interrupt executor has tasks waiting on timers.
+#[cfg(feature = "time")]
+pub(crate) fn timers_pending() -> bool {
+    executors().any(|e| e.has_timers.load(Ordering::Relaxed))
+}
+
+/// Pends every interrupt executor that has tasks waiting on timers, so that
+/// it can check whether they've expired. This is called whenever the tick
+/// counter advances.
+#[cfg(feature = "time")]
+pub(crate) fn pend_timer_executors() {
+    for e in executors() {
+        if e.has_timers.load(Ordering::Relaxed) {
+            (e.pend)();
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Tasks that can be torn down and rebuilt at runtime.
+
+use core::convert::Infallible;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use pin_project_lite::pin_project;
+
+use crate::atomic::{AtomicArithExt, AtomicExt};
+use super::Notify;
+
+/// Lets a supervisor restart a [`RestartableTask`] from anywhere, including
+/// another task or an ISR. This is usually a `static`, shared between the task
+/// slot and its supervisor.
+#[derive(Debug, Default)]
+pub struct RestartControl {
+    /// Set by `restart`, cleared when the slot acts on it.
+    requested: AtomicBool,
+    /// Number of times the slot has built a future, including the first.
+    builds: AtomicUsize,
+    /// Wakes the slot's task when a restart is requested.
+    notify: Notify,
+}
+
+impl RestartControl {
+    /// Creates a `RestartControl` with no restart pending.
+    pub const fn new() -> Self {
+        Self {
+            requested: AtomicBool::new(false),
+            builds: AtomicUsize::new(0),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Asks the task slot using this control to throw away its current future
+    /// and build a new one, and wakes it so that it does so promptly.
+    ///
+    /// The restart happens the next time the slot is polled, not during this
+    /// call -- so a task can safely restart itself, though it'll keep running
+    /// until it next returns `Pending`. Several restart requests before the
+    /// slot gets polled result in a single restart.
+    pub fn restart(&self) {
+        self.requested.store(true, Ordering::Release);
+        self.notify.notify();
+    }
+
+    /// Returns the number of times the task has been restarted so far.
+    pub fn restarts(&self) -> usize {
+        self.builds.load(Ordering::Relaxed).saturating_sub(1)
+    }
+}
+
+pin_project! {
+    /// A task slot that builds its future by calling a factory, and can build
+    /// it again on request.
+    ///
+    /// Normally, each task is a future pinned on `main`'s stack, and it's stuck
+    /// with whatever state it's in for the life of the program. A
+    /// `RestartableTask` holds a factory closure, and room for the future it
+    /// produces, and can be handed to the executor like any other task. When a
+    /// supervisor asks it to restart, through its [`RestartControl`], the slot
+    /// drops the current future in place -- running any cleanup in its
+    /// destructors, just as if it had been cancelled -- and calls the factory
+    /// to build a fresh one in the same storage. This gives you Erlang-style
+    /// recovery from a task that's gotten wedged, without a heap.
+    ///
+    /// ```ignore
+    /// static SENSOR_CONTROL: RestartControl = RestartControl::new();
+    ///
+    /// let sensor = pin!(RestartableTask::new(&SENSOR_CONTROL, || {
+    ///     sensor_task(&SENSOR_BUS)
+    /// }));
+    /// let supervisor = pin!(async {
+    ///     loop {
+    ///         if !sensor_healthy().await {
+    ///             SENSOR_CONTROL.restart();
+    ///         }
+    ///     }
+    /// });
+    ///
+    /// exec::run_tasks(&mut [sensor, supervisor], ALL_TASKS)
+    /// ```
+    ///
+    /// The factory is called again for each restart, so anything the task
+    /// needs has to be something the factory can produce more than once:
+    /// usually references to `static`s, or to things declared in `main` before
+    /// the task.
+    ///
+    /// The future isn't built until the slot is first polled, so the factory
+    /// runs inside the executor, like the rest of the task's code.
+    pub struct RestartableTask<'a, F, T> {
+        control: &'a RestartControl,
+        factory: F,
+        #[pin]
+        task: Option<T>,
+    }
+}
+
+impl<'a, F, T> RestartableTask<'a, F, T>
+where
+    F: FnMut() -> T,
+    T: Future<Output = Infallible>,
+{
+    /// Creates a task slot that's restarted through `control`, and that calls
+    /// `factory` to build the task's future.
+    ///
+    /// Each `RestartControl` should be used by only one slot.
+    pub fn new(control: &'a RestartControl, factory: F) -> Self {
+        Self {
+            control,
+            factory,
+            task: None,
+        }
+    }
+}
+
+impl<F, T> Future for RestartableTask<'_, F, T>
+where
+    F: FnMut() -> T,
+    T: Future<Output = Infallible>,
+{
+    type Output = Infallible;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Infallible> {
+        let mut p = self.project();
+        // Subscribe before checking the flag, so that a restart requested
+        // while we're polling the task wakes us again.
+        p.control.notify.subscribe(cx.waker());
+        if p.control.requested.swap_polyfill(false, Ordering::Acquire) {
+            // Drop the old future in place, running its cleanup.
+            p.task.set(None);
+        }
+        if p.task.is_none() {
+            p.task.set(Some((p.factory)()));
+            p.control.builds.fetch_add_polyfill(1, Ordering::Relaxed);
+        }
+        match p.task.as_pin_mut() {
+            Some(task) => task.poll(cx),
+            None => unreachable!(),
+        }
+    }
+}
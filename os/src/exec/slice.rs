@@ -0,0 +1,102 @@
+//! Time-sliced yielding, enabled by the `time-slice` feature.
+//!
+//! Long computations in a task need to `await` now and then to let other tasks
+//! run, but yielding on every iteration of a tight loop costs a trip through
+//! the executor each time. [`maybe_yield`] is a cheaper alternative: it only
+//! yields if the current task has been running for longer than the slice set
+//! with [`set_time_slice`], and otherwise lets the task carry on immediately.
+//!
+//! ```ignore
+//! exec::set_time_slice(CYCLES_PER_MS);
+//!
+//! for block in blocks {
+//!     checksum.update(block);
+//!     exec::maybe_yield().await;
+//! }
+//! ```
+//!
+//! Times are measured from the start of the task's current poll, with the same
+//! counter as task statistics; see the `stats` module for its units, and its
+//! caveats.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll};
+
+use super::timestamp;
+
+/// Length of a slice. Starts out at a value no poll can exceed, so nothing
+/// yields until a slice is set.
+static SLICE: AtomicU32 = AtomicU32::new(u32::MAX);
+/// Time at which the task currently being polled was polled.
+static POLL_START: AtomicU32 = AtomicU32::new(0);
+
+/// Sets how long a task can run before [`maybe_yield`] yields. This can be
+/// changed at any time.
+///
+/// `length` is in CPU cycles on processors with a cycle counter, and in ticks
+/// otherwise.
+///
+/// Until this is called, `maybe_yield` never yields.
+pub fn set_time_slice(length: u32) {
+    SLICE.store(length, Ordering::Relaxed);
+}
+
+/// Removes the slice set with [`set_time_slice`], so that [`maybe_yield`]
+/// never yields.
+pub fn clear_time_slice() {
+    SLICE.store(u32::MAX, Ordering::Relaxed);
+}
+
+/// Returns a future that yields, like [`yield_cpu`][super::yield_cpu], if the
+/// current task's poll has lasted longer than the slice set with
+/// [`set_time_slice`], and otherwise resolves immediately.
+///
+/// # Cancellation
+///
+/// **Cancel safety:** Strict.
+///
+/// Dropping this future does nothing in particular.
+pub fn maybe_yield() -> impl Future<Output = ()> {
+    MaybeYield { polled: false }
+}
+
+struct MaybeYield {
+    polled: bool,
+}
+
+impl Future for MaybeYield {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if core::mem::replace(&mut self.polled, true) {
+            return Poll::Ready(());
+        }
+        let elapsed = timestamp().wrapping_sub(POLL_START.load(Ordering::Relaxed));
+        if elapsed > SLICE.load(Ordering::Relaxed) {
+            // Ensure that we get called next round.
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+/// Called just before polling a task. Returns the start time of any poll
+/// that this one has interrupted, for `poll_finished`.
+#[inline(always)]
+pub(super) fn poll_started() -> u32 {
+    let outer = POLL_START.load(Ordering::Relaxed);
+    POLL_START.store(timestamp(), Ordering::Relaxed);
+    outer
+}
+
+/// Called just after polling a task, with the result of `poll_started`. This
+/// puts back the start time of the poll we interrupted, if this was a task on
+/// an interrupt executor.
+#[inline(always)]
+pub(super) fn poll_finished(outer: u32) {
+    POLL_START.store(outer, Ordering::Relaxed);
+}
@@ -0,0 +1,230 @@
+//! Per-task CPU accounting, enabled by the `stats` feature.
+//!
+//! To collect statistics, the application provides a [`TaskStatsTable`] with
+//! room for its tasks, and hands it to [`enable_task_stats`] before starting
+//! the executor. From then on, every poll of a task with an index that fits
+//! in the table is timed and counted, and the results can be read back with
+//! [`task_stats`].
+//!
+//! Poll durations are measured in CPU cycles on processors that have a DWT
+//! cycle counter (ARMv7-M, ARMv7E-M, and ARMv8-M mainline) and on RISC-V
+//! (using `mcycle`), and in ticks of the [`time`][crate::time] module
+//! otherwise. The cycle counter doesn't run out of reset; turn it on during
+//! startup using `cortex_m`'s `DCB::enable_trace` and
+//! `DWT::enable_cycle_counter`, or every duration will read as zero. Ticks
+//! are coarse, and the tick counter doesn't advance while interrupts are
+//! masked, so on processors measuring in ticks (ARMv6-M and ARMv8-M baseline)
+//! every poll under `Interrupts::Masked` takes zero ticks; only executors that
+//! allow preemption get useful numbers there.
+//!
+//! The same counter, with the same caveats, times poll budgets (the
+//! `poll-budget` feature), time slices (`time-slice`), and trace events.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use crate::arch;
+use crate::atomic::AtomicExt;
+use super::{timestamp, WakeMask};
+
+/// Statistics for a single task, as returned by [`task_stats`].
+///
+/// Counters wrap around rather than saturating.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TaskStats {
+    /// Number of times the task has been polled.
+    pub polls: u32,
+    /// Number of polls that happened even though nothing woke this task. This
+    /// happens when the task shares a wake bit with a task that _was_ woken
+    /// (see [`WakeMask`]), and can be fixed by enabling a larger wake bitmap.
+    pub spurious_polls: u32,
+    /// Total time spent polling the task.
+    pub total_poll_time: u64,
+    /// Longest single poll of the task.
+    pub max_poll_time: u32,
+}
+
+/// Statistics storage for a single task.
+#[derive(Debug)]
+pub(super) struct Slot {
+    stats: Cell<TaskStats>,
+    /// Set whenever something wakes this specific task; cleared when it's
+    /// polled. This is the only part of the slot that's touched from ISRs.
+    woken: AtomicBool,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            stats: Cell::new(TaskStats {
+                polls: 0,
+                spurious_polls: 0,
+                total_poll_time: 0,
+                max_poll_time: 0,
+            }),
+            woken: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Storage for the statistics of up to `N` tasks. Tasks with indices of `N`
+/// or greater aren't tracked.
+///
+/// This is intended to be stored in a `static`:
+///
+/// ```ignore
+/// static STATS: TaskStatsTable<4> = TaskStatsTable::new();
+///
+/// exec::enable_task_stats(&STATS);
+/// ```
+#[derive(Debug)]
+pub struct TaskStatsTable<const N: usize>([Slot; N]);
+
+// Safety: the `Cell`s in the table are only accessed inside critical
+// sections, which keeps an interrupt executor polling its tasks from tearing
+// a copy or update made by the Thread-mode executor or its tasks. The only
+// field touched outside of critical sections is `woken`, which is atomic.
+unsafe impl<const N: usize> Sync for TaskStatsTable<N> {}
+
+impl<const N: usize> TaskStatsTable<N> {
+    /// Creates a table with all statistics zeroed.
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const SLOT: Slot = Slot::new();
+        Self([SLOT; N])
+    }
+}
+
+impl<const N: usize> Default for TaskStatsTable<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Base of the registered table, if any. Only meaningful once `TABLE_LEN` is
+/// nonzero.
+static TABLE: AtomicPtr<Slot> = AtomicPtr::new(core::ptr::null_mut());
+/// Number of slots in the registered table. Written after `TABLE`, so that
+/// observing a nonzero length means the pointer is valid.
+static TABLE_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the registered table, or an empty slice if there isn't one.
+fn table() -> &'static [Slot] {
+    let len = TABLE_LEN.load(Ordering::Acquire);
+    if len == 0 {
+        return &[];
+    }
+    let ptr = TABLE.load(Ordering::Relaxed);
+    // Safety: TABLE and TABLE_LEN are only set, once, by `enable_task_stats`,
+    // from a `&'static` table of exactly that length.
+    unsafe { core::slice::from_raw_parts(ptr, len) }
+}
+
+/// Starts recording statistics for the tasks with indices that fit in
+/// `table`.
+///
+/// This should be called before starting the executor, so that the first
+/// polls of each task are counted correctly.
+///
+/// # Panics
+///
+/// If statistics have already been enabled.
+pub fn enable_task_stats<const N: usize>(table: &'static TaskStatsTable<N>) {
+    enable_slots(table.slots());
+}
+
+impl<const N: usize> TaskStatsTable<N> {
+    /// Returns the table's slots, without its size in the type.
+    pub(super) fn slots(&'static self) -> &'static [Slot] {
+        &self.0
+    }
+}
+
+/// Guts of `enable_task_stats`, which the `Executor` builder uses to enable a
+/// table it's been holding on to.
+pub(super) fn enable_slots(slots: &'static [Slot]) {
+    cheap_assert!(TABLE_LEN.load(Ordering::Relaxed) == 0);
+    TABLE.store(slots.as_ptr().cast_mut(), Ordering::Relaxed);
+    TABLE_LEN.store(slots.len(), Ordering::Release);
+}
+
+/// Returns the statistics collected for the task with the given `index`, or
+/// `None` if statistics aren't enabled or the task doesn't fit in the table.
+///
+/// A task calling this on itself won't see the poll that's in progress.
+///
+/// # Panics
+///
+/// If called from an ISR.
+pub fn task_stats(index: usize) -> Option<TaskStats> {
+    super::assert_not_in_isr();
+    let slot = table().get(index)?;
+    Some(arch::critical_section(|| slot.stats.get()))
+}
+
+/// Zeroes the statistics for all tasks, e.g. to start a new measurement
+/// window.
+///
+/// # Panics
+///
+/// If called from an ISR.
+pub fn reset_task_stats() {
+    super::assert_not_in_isr();
+    arch::critical_section(|| {
+        for slot in table() {
+            slot.stats.set(TaskStats::default());
+        }
+    });
+}
+
+/// Records that the task with the given `index` has been woken.
+#[inline(always)]
+pub(super) fn note_wake(index: usize) {
+    if let Some(slot) = table().get(index) {
+        slot.woken.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Records that every task named in `mask` has been woken.
+pub(super) fn note_wake_mask(mask: &WakeMask) {
+    for (i, slot) in table().iter().enumerate() {
+        if mask.contains(i) {
+            slot.woken.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// State captured at the start of a poll, for use by `poll_finished`.
+pub(super) struct PollStart {
+    woken: bool,
+    time: u32,
+}
+
+/// Called just before polling the task with the given `index`.
+#[inline(always)]
+pub(super) fn poll_started(index: usize) -> PollStart {
+    let woken = match table().get(index) {
+        Some(slot) => slot.woken.swap_polyfill(false, Ordering::Relaxed),
+        None => true,
+    };
+    PollStart { woken, time: timestamp() }
+}
+
+/// Called just after polling the task with the given `index`.
+#[inline(always)]
+pub(super) fn poll_finished(index: usize, start: PollStart) {
+    let elapsed = timestamp().wrapping_sub(start.time);
+    if let Some(slot) = table().get(index) {
+        arch::critical_section(|| {
+            let mut s = slot.stats.get();
+            s.polls = s.polls.wrapping_add(1);
+            if !start.woken {
+                s.spurious_polls = s.spurious_polls.wrapping_add(1);
+            }
+            s.total_poll_time =
+                s.total_poll_time.wrapping_add(u64::from(elapsed));
+            s.max_poll_time = s.max_poll_time.max(elapsed);
+            slot.stats.set(s);
+        });
+    }
+}
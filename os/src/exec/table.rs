@@ -0,0 +1,263 @@
+//! Named task tables, so that task indices don't have to be kept in sync by
+//! hand.
+
+use super::{wake_task_by_index, WakeMask};
+
+/// Identifies a task by its index in the executor's task array. These are
+/// usually declared by [`task_table!`][crate::task_table], rather than built
+/// by hand.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TaskId(usize);
+
+impl TaskId {
+    /// Creates a `TaskId` for the task at position `index`.
+    pub const fn new(index: usize) -> Self {
+        Self(index)
+    }
+
+    /// Returns the task's index.
+    pub const fn index(self) -> usize {
+        self.0
+    }
+
+    /// Returns a `WakeMask` naming just this task.
+    pub const fn mask(self) -> WakeMask {
+        WakeMask::new().with_task(self.0)
+    }
+
+    /// Wakes this task. This is equivalent to
+    /// [`wake_task_by_index`][super::wake_task_by_index], and has the same
+    /// caveats.
+    pub fn wake(self) {
+        wake_task_by_index(self.0);
+    }
+}
+
+impl From<TaskId> for WakeMask {
+    fn from(id: TaskId) -> Self {
+        id.mask()
+    }
+}
+
+/// Declares a module of named [`TaskId`][crate::exec::TaskId] constants, one
+/// per task, numbered in order from zero. The module also gets a `COUNT` of
+/// the tasks, and an `ALL` mask naming every one of them.
+///
+/// ```ignore
+/// lilos::task_table! {
+///     pub mod tasks {
+///         /// Doc comments and other attributes are allowed on each task.
+///         uart_rx,
+///         #[cfg(feature = "blinky")]
+///         blinky,
+///         logger,
+///     }
+/// }
+///
+/// assert_eq!(tasks::logger.index(), tasks::COUNT - 1);
+/// ```
+///
+/// A task that's left out by a `#[cfg]` attribute doesn't take up an index,
+/// and isn't counted in `COUNT` or `ALL`.
+///
+/// Pass the tasks to the executor with
+/// [`run_task_table!`][crate::run_task_table], which makes sure they're in
+/// this order. See the [`exec`][crate::exec] module docs for more.
+#[macro_export]
+macro_rules! task_table {
+    (
+        $(#[$attr:meta])*
+        $vis:vis mod $module:ident {
+            $($(#[$task_attr:meta])* $name:ident),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[allow(non_upper_case_globals)]
+        $vis mod $module {
+            /// Numbers the tasks. Being an enum, this skips any tasks that
+            /// are configured out, and `__Count` ends up after the last task
+            /// that isn't.
+            #[allow(non_camel_case_types, dead_code)]
+            enum __Index {
+                $($(#[$task_attr])* $name,)*
+                __Count,
+            }
+
+            $(
+                $(#[$task_attr])*
+                pub const $name: $crate::exec::TaskId =
+                    $crate::exec::TaskId::new(__Index::$name as usize);
+            )*
+
+            /// Number of tasks in the table.
+            pub const COUNT: usize = __Index::__Count as usize;
+
+            /// Mask naming every task in the table.
+            pub const ALL: $crate::exec::WakeMask = {
+                let mut mask = $crate::exec::WakeMask::new();
+                let mut i = 0;
+                while i < COUNT {
+                    mask = mask.with_task(i);
+                    i += 1;
+                }
+                mask
+            };
+        }
+    };
+}
+
+/// Starts the executor with the tasks in a [`task_table!`], given as a future
+/// for each name, in the table's order. This is checked at compile time.
+///
+/// By default, every task is started. To start only some of them, add
+/// `start: [name, ...]`. An idle hook or idle task can be added with
+/// `idle_hook: expr` or `idle_task: expr`, which choose between
+/// [`run_tasks_with_idle`][crate::exec::run_tasks_with_idle] and
+/// [`run_tasks_with_idle_task`][crate::exec::run_tasks_with_idle_task]:
+///
+/// ```ignore
+/// lilos::run_task_table! {
+///     tasks {
+///         uart_rx: uart_rx_task(&UART),
+///         #[cfg(feature = "blinky")]
+///         blinky: blink(&LED),
+///         logger: log_task(),
+///     }
+///     start: [uart_rx]
+///     idle_hook: || cortex_m::asm::wfi()
+/// }
+/// ```
+///
+/// Tasks can have `#[cfg]` attributes, which need to match the ones in the
+/// `task_table!`.
+///
+/// To start the executor some other way, such as with the
+/// [`Executor`][crate::exec::Executor] builder, use `run: |tasks, start|
+/// expr` instead. The closure-like expression is given the task array and the
+/// mask of tasks to start, and has to start the executor itself.
+///
+/// The table can be given as a path, like `crate::tasks`. Like `run_tasks`,
+/// this never returns.
+#[macro_export]
+macro_rules! run_task_table {
+    (
+        $($table:ident)::+ {
+            $($(#[cfg($cfg:meta)])* $name:ident : $task:expr),* $(,)?
+        }
+        $(start: [$($start:ident),* $(,)?])?
+    ) => {
+        $crate::run_task_table!(
+            @run [$($table)::+] [$($(#[cfg($cfg)])* $name : $task),*]
+                [$($($start),*)?],
+            |tasks, start| $crate::exec::run_tasks(tasks, start)
+        )
+    };
+    (
+        $($table:ident)::+ {
+            $($(#[cfg($cfg:meta)])* $name:ident : $task:expr),* $(,)?
+        }
+        $(start: [$($start:ident),* $(,)?])?
+        idle_hook: $hook:expr $(,)?
+    ) => {
+        $crate::run_task_table!(
+            @run [$($table)::+] [$($(#[cfg($cfg)])* $name : $task),*]
+                [$($($start),*)?],
+            |tasks, start| $crate::exec::run_tasks_with_idle(tasks, start, $hook)
+        )
+    };
+    (
+        $($table:ident)::+ {
+            $($(#[cfg($cfg:meta)])* $name:ident : $task:expr),* $(,)?
+        }
+        $(start: [$($start:ident),* $(,)?])?
+        idle_task: $idle:expr $(,)?
+    ) => {
+        $crate::run_task_table!(
+            @run [$($table)::+] [$($(#[cfg($cfg)])* $name : $task),*]
+                [$($($start),*)?],
+            |tasks, start| $crate::exec::run_tasks_with_idle_task(
+                tasks,
+                start,
+                core::pin::pin!($idle),
+            )
+        )
+    };
+    (
+        $($table:ident)::+ {
+            $($(#[cfg($cfg:meta)])* $name:ident : $task:expr),* $(,)?
+        }
+        $(start: [$($start:ident),* $(,)?])?
+        run: |$tasks:ident, $start_mask:ident| $run:expr $(,)?
+    ) => {
+        $crate::run_task_table!(
+            @run [$($table)::+] [$($(#[cfg($cfg)])* $name : $task),*]
+                [$($($start),*)?],
+            |$tasks, $start_mask| $run
+        )
+    };
+    (
+        @run [$($table:ident)::+]
+            [$($(#[cfg($cfg:meta)])* $name:ident : $task:expr),*]
+            [$($start:ident),*],
+        |$tasks:ident, $start_mask:ident| $run:expr
+    ) => {{
+        use $($table)::+ as __lilos_task_table;
+
+        const _: () = {
+            let mut index = 0;
+            $(
+                $(#[cfg($cfg)])*
+                {
+                    assert!(
+                        __lilos_task_table::$name.index() == index,
+                        concat!(
+                            "task `", stringify!($name),
+                            "` isn't in the same place as in its task_table!",
+                        ),
+                    );
+                    index += 1;
+                }
+            )*
+            assert!(
+                index == __lilos_task_table::COUNT,
+                "run_task_table! is missing tasks from its task_table!",
+            );
+        };
+
+        let $tasks: &mut [core::pin::Pin<
+            &mut dyn core::future::Future<Output = core::convert::Infallible>,
+        >] = &mut [$($(#[cfg($cfg)])* core::pin::pin!($task)),*];
+        let $start_mask = $crate::run_task_table!(
+            @start __lilos_task_table [$($start),*]
+        );
+        $run
+    }};
+    (@start $table:ident []) => { $table::ALL };
+    (@start $table:ident [$($start:ident),+]) => {
+        $crate::exec::WakeMask::new()$(.with_task($table::$start.index()))+
+    };
+}
+
+/// Wakes a task named in a [`task_table!`]: `wake_task!(tasks::uart_rx)`, or
+/// just `wake_task!(uart_rx)` if the table's contents have been imported.
+#[macro_export]
+macro_rules! wake_task {
+    ($task:expr) => {
+        $crate::exec::TaskId::wake($task)
+    };
+}
+
+/// Builds a [`WakeMask`][crate::exec::WakeMask] naming tasks from a
+/// [`task_table!`]. This is a constant expression, so it can be used to
+/// define constants.
+///
+/// ```ignore
+/// const SENSORS: WakeMask = lilos::task_mask!(tasks::imu, tasks::baro);
+/// ```
+#[macro_export]
+macro_rules! task_mask {
+    ($($task:expr),* $(,)?) => {
+        $crate::exec::WakeMask::new()
+            $(.with_task($crate::exec::TaskId::index($task)))*
+    };
+}
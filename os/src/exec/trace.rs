@@ -0,0 +1,256 @@
+//! Executor event tracing, enabled by the `trace` feature.
+//!
+//! When a sink has been installed with [`set_trace_sink`], the executor
+//! reports a [`TraceEvent`] each time it polls a task, each time a task is
+//! woken, around each run of the idle hook, and whenever timers expire. This
+//! is enough to reconstruct a timeline of what the scheduler did, which is
+//! handy when you're trying to figure out why some task responded late.
+//!
+//! You can write your own [`TraceSink`] to forward events to an external
+//! recorder (such as one that streams over RTT), or use the provided
+//! [`TraceRing`], which keeps the most recent events in a fixed-size buffer in
+//! RAM where a debugger -- or your own code, after a fault -- can find them.
+//!
+//! With the feature turned off, none of this exists and the executor contains
+//! no tracing code. With the feature on but no sink installed, each event
+//! costs a load and a branch.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::atomic::AtomicArithExt;
+use super::timestamp;
+
+/// Something the executor did, as reported to a [`TraceSink`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceEvent {
+    /// The executor is about to poll the task with the given index.
+    PollStart(usize),
+    /// The executor has finished polling the task with the given index.
+    PollEnd(usize),
+    /// The task with the given index was woken, either through its `Waker` or
+    /// by a call to [`wake_task_by_index`][super::wake_task_by_index].
+    WakeTask(usize),
+    /// Tasks were woken by [`wake_tasks_by_mask`][super::wake_tasks_by_mask]
+    /// (which is also how [`Notify`][super::Notify] wakes tasks). This is
+    /// reported one word of the [`WakeMask`][super::WakeMask] at a time: `bits`
+    /// gives the tasks named in word number `word`. Words with no bits set
+    /// aren't reported.
+    WakeTasks {
+        /// Index of the word within the wake mask.
+        word: usize,
+        /// Contents of that word.
+        bits: usize,
+    },
+    /// No tasks are ready, and the executor is calling the idle hook.
+    IdleStart,
+    /// The idle hook has returned.
+    IdleEnd,
+    /// One or more timers have expired, and their tasks are being woken. The
+    /// resulting `WakeTask` events follow this one.
+    #[cfg(feature = "time")]
+    TimersFired,
+}
+
+/// Destination for trace events.
+///
+/// Events can be reported from ISRs (when they wake tasks) as well as from the
+/// executor, so `record` must be safe to call from any context, and should be
+/// quick about it.
+pub trait TraceSink: Sync {
+    /// Records a single event.
+    fn record(&self, event: TraceEvent);
+}
+
+/// The installed sink. Only read once `SINK_SET` has been observed to be true.
+static mut SINK: Option<&'static dyn TraceSink> = None;
+/// Set, once, after `SINK` has been written.
+static SINK_SET: AtomicBool = AtomicBool::new(false);
+
+/// Installs `sink` to receive executor events from now on.
+///
+/// This is normally called before starting the executor, so that the trace
+/// starts from the beginning.
+///
+/// # Panics
+///
+/// If a sink has already been installed, or if called from an ISR.
+pub fn set_trace_sink(sink: &'static dyn TraceSink) {
+    super::assert_not_in_isr();
+    cheap_assert!(!SINK_SET.load(Ordering::Relaxed));
+    // Safety: nobody reads SINK until SINK_SET is true, and we're the only
+    // writer, since we've just checked that SINK_SET is false (and ISRs can't
+    // get here).
+    unsafe {
+        SINK = Some(sink);
+    }
+    SINK_SET.store(true, Ordering::Release);
+}
+
+/// Reports `event` to the installed sink, if any.
+#[inline(always)]
+pub(crate) fn emit(event: TraceEvent) {
+    if SINK_SET.load(Ordering::Acquire) {
+        // Safety: SINK is never written again once SINK_SET is true.
+        if let Some(sink) = unsafe { SINK } {
+            sink.record(event);
+        }
+    }
+}
+
+/// A trace event, with the time at which it was recorded, as retrieved from a
+/// [`TraceRing`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TraceRecord {
+    /// Sequence number of the event, counting from zero. Gaps in the sequence
+    /// mean that events were overwritten, or were in the middle of being
+    /// recorded when the ring was read.
+    pub seq: usize,
+    /// Time at which the event was recorded. This uses the same units as the
+    /// task statistics: CPU cycles if the processor has a DWT cycle counter
+    /// (which you must enable), and ticks otherwise.
+    pub time: u32,
+    /// The event itself.
+    pub event: TraceEvent,
+}
+
+/// Marks a slot as being written.
+const SEQ_BUSY: usize = usize::MAX;
+
+/// One slot of a `TraceRing`. Everything is atomic so that ISRs and the
+/// executor can record events concurrently.
+#[derive(Debug)]
+struct Slot {
+    seq: AtomicUsize,
+    time: AtomicU32,
+    kind: AtomicU32,
+    a: AtomicUsize,
+    b: AtomicUsize,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            seq: AtomicUsize::new(SEQ_BUSY),
+            time: AtomicU32::new(0),
+            kind: AtomicU32::new(0),
+            a: AtomicUsize::new(0),
+            b: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// A [`TraceSink`] that keeps the most recent `N` events in RAM.
+///
+/// This is intended to be stored in a `static`, so that it survives until you
+/// can get a look at it:
+///
+/// ```ignore
+/// static TRACE: TraceRing<64> = TraceRing::new();
+///
+/// exec::set_trace_sink(&TRACE);
+/// ```
+///
+/// Recording is lock-free, so events reported from ISRs can't corrupt the
+/// ring, but they can interleave with events reported by the executor. Reading
+/// the ring with [`records`][TraceRing::records] skips any slot that's being
+/// overwritten at the time.
+#[derive(Debug)]
+pub struct TraceRing<const N: usize> {
+    next: AtomicUsize,
+    slots: [Slot; N],
+}
+
+impl<const N: usize> TraceRing<N> {
+    /// Creates an empty ring.
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const SLOT: Slot = Slot::new();
+        Self {
+            next: AtomicUsize::new(0),
+            slots: [SLOT; N],
+        }
+    }
+
+    /// Returns the number of events recorded so far, including any that have
+    /// been overwritten.
+    pub fn total(&self) -> usize {
+        self.next.load(Ordering::Acquire)
+    }
+
+    /// Returns the events currently in the ring, oldest first.
+    pub fn records(&self) -> impl Iterator<Item = TraceRecord> + '_ {
+        let end = self.total();
+        let start = end.saturating_sub(N);
+        (start..end).filter_map(move |seq| self.read(seq))
+    }
+
+    /// Reads the record with the given sequence number, if it's still in the
+    /// ring and not being overwritten.
+    fn read(&self, seq: usize) -> Option<TraceRecord> {
+        let slot = &self.slots[seq % N];
+        if slot.seq.load(Ordering::Acquire) != seq {
+            return None;
+        }
+        let time = slot.time.load(Ordering::Relaxed);
+        let kind = slot.kind.load(Ordering::Relaxed);
+        let a = slot.a.load(Ordering::Relaxed);
+        let b = slot.b.load(Ordering::Relaxed);
+        // Check that nobody started overwriting the slot while we read it.
+        if slot.seq.load(Ordering::Acquire) != seq {
+            return None;
+        }
+        Some(TraceRecord { seq, time, event: decode(kind, a, b)? })
+    }
+}
+
+impl<const N: usize> Default for TraceRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> TraceSink for TraceRing<N> {
+    fn record(&self, event: TraceEvent) {
+        if N == 0 {
+            return;
+        }
+        let seq = self.next.fetch_add_polyfill(1, Ordering::Relaxed);
+        let slot = &self.slots[seq % N];
+        let (kind, a, b) = encode(event);
+        slot.seq.store(SEQ_BUSY, Ordering::Release);
+        slot.time.store(timestamp(), Ordering::Relaxed);
+        slot.kind.store(kind, Ordering::Relaxed);
+        slot.a.store(a, Ordering::Relaxed);
+        slot.b.store(b, Ordering::Relaxed);
+        slot.seq.store(seq, Ordering::Release);
+    }
+}
+
+/// Packs an event into words for storage in a `TraceRing`.
+fn encode(event: TraceEvent) -> (u32, usize, usize) {
+    match event {
+        TraceEvent::PollStart(i) => (0, i, 0),
+        TraceEvent::PollEnd(i) => (1, i, 0),
+        TraceEvent::WakeTask(i) => (2, i, 0),
+        TraceEvent::WakeTasks { word, bits } => (3, word, bits),
+        TraceEvent::IdleStart => (4, 0, 0),
+        TraceEvent::IdleEnd => (5, 0, 0),
+        #[cfg(feature = "time")]
+        TraceEvent::TimersFired => (6, 0, 0),
+    }
+}
+
+/// Inverse of `encode`.
+fn decode(kind: u32, a: usize, b: usize) -> Option<TraceEvent> {
+    Some(match kind {
+        0 => TraceEvent::PollStart(a),
+        1 => TraceEvent::PollEnd(a),
+        2 => TraceEvent::WakeTask(a),
+        3 => TraceEvent::WakeTasks { word: a, bits: b },
+        4 => TraceEvent::IdleStart,
+        5 => TraceEvent::IdleEnd,
+        #[cfg(feature = "time")]
+        6 => TraceEvent::TimersFired,
+        _ => return None,
+    })
+}
@@ -0,0 +1,486 @@
+//! Per-task memory isolation, enabled by the `task-isolation` feature.
+//!
+//! All tasks share one address space, so a stray pointer in one task can
+//! corrupt another task's state -- and the symptoms usually show up in the
+//! victim, not the culprit. On processors with a memory protection unit, the
+//! executor can help: if you install a [`TaskIsolation`] implementation with
+//! [`set_task_isolation`] (or the `Executor` builder's `task_isolation`), the
+//! executor calls it just before polling each task, telling it which task is
+//! about to run and where that task's future lives, and again just after. The
+//! implementation reconfigures the MPU so that, for the duration of the poll,
+//! the task can only touch its own future plus any regions you've explicitly
+//! shared with it.
+//!
+//! Two implementations are provided:
+//!
+//! - [`CortexMMpu`] programs the ARMv7-M MPU (Cortex-M3, M4, and M7), and is
+//!   only available on those targets.
+//! - [`SoftMpu`] doesn't touch any hardware. Instead, it keeps track of what
+//!   the current task may access, and lets you check accesses against that by
+//!   hand. This is a stand-in for hosted builds and tests, where there's no
+//!   MPU.
+//!
+//! When a task does touch something it shouldn't, the processor takes a
+//! MemManage fault. Calling [`isolated_task`] from the fault handler tells you
+//! which task was running. (Turn on MemManage faults during startup, or they
+//! escalate to HardFault, where `isolated_task` still works but the reason for
+//! the fault is less obvious.)
+//!
+//! # Caveats
+//!
+//! Only memory is covered: a task can still disable interrupts or reprogram
+//! peripherals, since `lilos` runs everything privileged.
+//!
+//! The OS touches its own `static`s (the wake bitmap, the timer list, and so
+//! on) on every task's behalf, and there's no way to name them to share them,
+//! so the protected memory can't include them. In practice, this means
+//! protecting an area of RAM set aside for application state, like a second
+//! RAM bank or a linker section of your own, rather than all of RAM. The same
+//! goes for the main stack, which every task runs on: if it's protected, it
+//! needs to be shared with every task.
+//!
+//! If you allow interrupts to preempt task code, ISRs run with the restrictions
+//! of whichever task they interrupted.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::arch::critical_section;
+use crate::exec::WakeMask;
+
+/// A range of memory addresses.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Region {
+    /// Address of the first byte in the region.
+    pub base: usize,
+    /// Length of the region in bytes.
+    pub len: usize,
+}
+
+impl Region {
+    /// Creates a region of `len` bytes starting at `base`.
+    pub const fn new(base: usize, len: usize) -> Self {
+        Self { base, len }
+    }
+
+    /// Creates a region covering exactly the memory occupied by `value`.
+    pub fn of<T: ?Sized>(value: &T) -> Self {
+        let ptr: *const T = value;
+        Self {
+            base: ptr.cast::<u8>() as usize,
+            len: core::mem::size_of_val(value),
+        }
+    }
+
+    /// Checks whether all of `other` lies within this region.
+    pub fn contains(&self, other: Region) -> bool {
+        match other.base.checked_sub(self.base) {
+            Some(offset) => offset <= self.len && other.len <= self.len - offset,
+            None => false,
+        }
+    }
+}
+
+/// Something that can restrict memory access to what a single task is allowed
+/// to touch, such as an MPU driver. See the [module docs](self).
+///
+/// Both methods are called by the executor, from the context that's polling
+/// the task: thread mode, usually, but inside the ISR for tasks on an
+/// interrupt executor.
+pub trait TaskIsolation: Sync {
+    /// Restricts memory access to what task `task` may touch, given that its
+    /// future occupies `storage`. This is called just before the task is
+    /// polled.
+    ///
+    /// This may also be called without a `leave` in between, when the poll of
+    /// a task on an interrupt executor finishes and the executor goes back to
+    /// the poll it interrupted.
+    fn enter(&self, task: usize, storage: Region);
+
+    /// Lifts the restrictions put in place for `task`. This is called just
+    /// after the task is polled.
+    fn leave(&self, task: usize);
+}
+
+/// The installed isolation implementation. Only read once `ISOLATION_SET` has
+/// been observed to be true.
+static mut ISOLATION: Option<&'static dyn TaskIsolation> = None;
+/// Set, once, after `ISOLATION` has been written.
+static ISOLATION_SET: AtomicBool = AtomicBool::new(false);
+
+/// Value of `CURRENT_TASK` when no task is being polled.
+const NO_TASK: usize = usize::MAX;
+/// Index of the task being polled, or `NO_TASK`.
+static CURRENT_TASK: AtomicUsize = AtomicUsize::new(NO_TASK);
+/// Storage of the task being polled, if any.
+static CURRENT_BASE: AtomicUsize = AtomicUsize::new(0);
+/// Length to go with `CURRENT_BASE`.
+static CURRENT_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Installs `isolation`, to be applied around every task poll from now on.
+///
+/// This is normally called before starting the executor.
+///
+/// # Panics
+///
+/// If isolation has already been installed.
+pub fn set_task_isolation(isolation: &'static dyn TaskIsolation) {
+    crate::cheap_assert!(!ISOLATION_SET.load(Ordering::Relaxed));
+    // Safety: nobody reads ISOLATION until ISOLATION_SET is true, and we're
+    // the only writer, since we've just checked that ISOLATION_SET is false.
+    unsafe {
+        ISOLATION = Some(isolation);
+    }
+    ISOLATION_SET.store(true, Ordering::Release);
+}
+
+/// Returns the installed isolation implementation, if any.
+#[inline(always)]
+fn installed() -> Option<&'static dyn TaskIsolation> {
+    if ISOLATION_SET.load(Ordering::Acquire) {
+        // Safety: ISOLATION is never written again once ISOLATION_SET is true.
+        unsafe { ISOLATION }
+    } else {
+        None
+    }
+}
+
+/// Returns the index of the task being polled, if any. This is meant for
+/// fault handlers, to find out which task caused a fault.
+///
+/// Tasks on an `InterruptExecutor` are reported by their index in the task
+/// numbering shared by all executors, the same index their `Waker`s use.
+pub fn isolated_task() -> Option<usize> {
+    match CURRENT_TASK.load(Ordering::Relaxed) {
+        NO_TASK => None,
+        task => Some(task),
+    }
+}
+
+/// The poll that was in progress when another one started, as returned by
+/// `poll_started`.
+pub(crate) struct Interrupted {
+    task: usize,
+    storage: Region,
+}
+
+/// Called just before polling the task with the given `index`, whose future
+/// occupies `storage`.
+#[inline(always)]
+pub(crate) fn poll_started(index: usize, storage: Region) -> Interrupted {
+    let outer = Interrupted {
+        task: CURRENT_TASK.load(Ordering::Relaxed),
+        storage: Region::new(
+            CURRENT_BASE.load(Ordering::Relaxed),
+            CURRENT_LEN.load(Ordering::Relaxed),
+        ),
+    };
+    set_current(index, storage);
+    if let Some(isolation) = installed() {
+        isolation.enter(index, storage);
+    }
+    outer
+}
+
+/// Called just after polling the task with the given `index`, with the result
+/// of `poll_started`. If that poll interrupted another one, this puts back its
+/// restrictions.
+#[inline(always)]
+pub(crate) fn poll_finished(index: usize, outer: Interrupted) {
+    set_current(outer.task, outer.storage);
+    if let Some(isolation) = installed() {
+        if outer.task == NO_TASK {
+            isolation.leave(index);
+        } else {
+            isolation.enter(outer.task, outer.storage);
+        }
+    }
+}
+
+fn set_current(task: usize, storage: Region) {
+    CURRENT_TASK.store(task, Ordering::Relaxed);
+    CURRENT_BASE.store(storage.base, Ordering::Relaxed);
+    CURRENT_LEN.store(storage.len, Ordering::Relaxed);
+}
+
+/// Up to `S` regions, each shared with some set of tasks.
+#[derive(Debug)]
+struct Grants<const S: usize>([Cell<Option<(Region, WakeMask)>>; S]);
+
+impl<const S: usize> Grants<S> {
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: Cell<Option<(Region, WakeMask)>> = Cell::new(None);
+        Self([EMPTY; S])
+    }
+
+    /// Records that the tasks in `tasks` may access `region`. Panics if
+    /// there's no room.
+    fn share(&self, region: Region, tasks: WakeMask) {
+        let mut grant = Some((region, tasks));
+        critical_section(|| {
+            if let Some(slot) = self.0.iter().find(|g| g.get().is_none()) {
+                slot.set(grant.take());
+            }
+        });
+        crate::cheap_assert!(grant.is_none());
+    }
+
+    /// Calls `body` with each region shared with `task`.
+    fn for_task(&self, task: usize, mut body: impl FnMut(Region)) {
+        for slot in &self.0 {
+            if let Some((region, tasks)) = critical_section(|| slot.get()) {
+                if tasks.contains(task) {
+                    body(region);
+                }
+            }
+        }
+    }
+}
+
+/// A software stand-in for an MPU, for hosted builds and tests. See the
+/// [module docs](self).
+///
+/// This keeps track of the regions each task may access, plus the task being
+/// polled, and checks accesses that you describe to it with
+/// [`check`][SoftMpu::check]. It has room for `S` shared regions.
+///
+/// This is intended to be stored in a `static`:
+///
+/// ```ignore
+/// static MPU: SoftMpu<4> = SoftMpu::new();
+///
+/// MPU.share(Region::of(&SHARED_STATE), task_mask!(tasks::a, tasks::b));
+/// isolation::set_task_isolation(&MPU);
+/// ```
+#[derive(Debug)]
+pub struct SoftMpu<const S: usize> {
+    grants: Grants<S>,
+    /// Task being polled, and its storage.
+    current: Cell<Option<(usize, Region)>>,
+}
+
+// Safety: the `Cell`s are only accessed inside critical sections.
+unsafe impl<const S: usize> Sync for SoftMpu<S> {}
+
+impl<const S: usize> SoftMpu<S> {
+    /// Creates a `SoftMpu` with nothing shared.
+    pub const fn new() -> Self {
+        Self {
+            grants: Grants::new(),
+            current: Cell::new(None),
+        }
+    }
+
+    /// Lets the tasks named in `tasks` access `region`, in addition to their
+    /// own futures.
+    ///
+    /// # Panics
+    ///
+    /// If `S` regions have already been shared.
+    pub fn share(&self, region: Region, tasks: impl Into<WakeMask>) {
+        self.grants.share(region, tasks.into());
+    }
+
+    /// Checks whether the task being polled may access `access`, returning
+    /// the details if it may not. Outside of task polls, everything is
+    /// accessible.
+    pub fn check(&self, access: Region) -> Result<(), AccessFault> {
+        let Some((task, storage)) = critical_section(|| self.current.get()) else {
+            return Ok(());
+        };
+        let mut allowed = storage.contains(access);
+        self.grants.for_task(task, |region| {
+            allowed |= region.contains(access);
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(AccessFault { task, access })
+        }
+    }
+
+    /// Checks whether the task being polled may access `value`. This is
+    /// equivalent to `check(Region::of(value))`.
+    pub fn check_ref<T: ?Sized>(&self, value: &T) -> Result<(), AccessFault> {
+        self.check(Region::of(value))
+    }
+}
+
+impl<const S: usize> Default for SoftMpu<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const S: usize> TaskIsolation for SoftMpu<S> {
+    fn enter(&self, task: usize, storage: Region) {
+        critical_section(|| self.current.set(Some((task, storage))));
+    }
+
+    fn leave(&self, _task: usize) {
+        critical_section(|| self.current.set(None));
+    }
+}
+
+/// An access that [`SoftMpu::check`] found the current task isn't allowed to
+/// make.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AccessFault {
+    /// Index of the task that made the access.
+    pub task: usize,
+    /// The memory it tried to access.
+    pub access: Region,
+}
+
+/// Drives the ARMv7-M MPU to isolate tasks. See the [module docs](self).
+///
+/// While a task is polled, this makes a `protected` region of memory
+/// inaccessible, except for the task's own future and any regions shared with
+/// it. Memory outside `protected` is left alone. Since the OS's own `static`s
+/// can't be shared, `protected` must not include them; see the [module
+/// docs](self#caveats). It has room for `S` shared regions, and each task can
+/// be given two fewer of them than the MPU has regions: six, usually.
+///
+/// The ARMv7-M MPU can only protect regions that are a power of two in size,
+/// at least 32 bytes, and aligned to their size, so `protected` and any
+/// shared regions have to be like that. Task futures generally aren't, so
+/// each task is given access to the smallest such region that covers its
+/// future, which may include some of its neighbors.
+///
+/// This takes over the MPU entirely while tasks are running, and turns it off
+/// between polls. It panics the first time a task is polled if the processor
+/// turns out not to have an MPU.
+#[cfg(feature = "has-pmsav7")]
+#[derive(Debug)]
+pub struct CortexMMpu<const S: usize> {
+    protected: Region,
+    grants: Grants<S>,
+}
+
+// Safety: the `Cell`s in `grants` are only accessed inside critical sections.
+#[cfg(feature = "has-pmsav7")]
+unsafe impl<const S: usize> Sync for CortexMMpu<S> {}
+
+#[cfg(feature = "has-pmsav7")]
+impl<const S: usize> CortexMMpu<S> {
+    /// MPU_CTRL: turn on the MPU, and use the default memory map for anything
+    /// not covered by a region.
+    const CTRL_ENABLE: u32 = 1 << 0 | 1 << 2;
+    /// RASR access permissions: none at all.
+    const AP_NONE: u32 = 0b000 << 24;
+    /// RASR access permissions: read and write.
+    const AP_RW: u32 = 0b011 << 24;
+    /// RASR memory attributes: normal, shareable, write-through, as suits
+    /// on-chip RAM.
+    const NORMAL_MEMORY: u32 = 1 << 18 | 1 << 17;
+
+    /// Creates a `CortexMMpu` protecting `protected`, with nothing shared.
+    ///
+    /// # Panics
+    ///
+    /// If `protected` isn't a region the MPU can describe.
+    pub const fn new(protected: Region) -> Self {
+        assert!(Self::describable(protected));
+        Self {
+            protected,
+            grants: Grants::new(),
+        }
+    }
+
+    /// Lets the tasks named in `tasks` access `region`, in addition to their
+    /// own futures.
+    ///
+    /// # Panics
+    ///
+    /// If `region` isn't a region the MPU can describe, or if `S` regions have
+    /// already been shared.
+    pub fn share(&self, region: Region, tasks: impl Into<WakeMask>) {
+        crate::cheap_assert!(Self::describable(region));
+        self.grants.share(region, tasks.into());
+    }
+
+    /// Checks that `region` is a power of two in size, at least 32 bytes, and
+    /// aligned to its size.
+    const fn describable(region: Region) -> bool {
+        region.len.is_power_of_two()
+            && region.len >= 32
+            && region.base & (region.len - 1) == 0
+    }
+
+    /// Returns the smallest region the MPU can describe that covers `region`.
+    fn covering(region: Region) -> Region {
+        let end = region.base + region.len;
+        let mut len = region.len.max(32).next_power_of_two();
+        loop {
+            let base = region.base & !(len - 1);
+            if base + len >= end {
+                break Region::new(base, len);
+            }
+            len *= 2;
+        }
+    }
+
+    /// Programs MPU region number `number` to cover `region`, which must be
+    /// describable, with access permissions `ap`.
+    ///
+    /// # Safety
+    ///
+    /// The MPU must be disabled, or this must be a region whose change is
+    /// harmless. This must be called in a critical section, so that nothing
+    /// else can reprogram the MPU between our register writes.
+    unsafe fn set_region(number: u32, region: Region, ap: u32) {
+        // Safety: we own the MPU, per the type's docs.
+        let mpu = unsafe { &*cortex_m::peripheral::MPU::PTR };
+        // RASR.SIZE is one less than the log2 of the size.
+        let size = (region.len.trailing_zeros() - 1) << 1;
+        unsafe {
+            // RBAR: address, VALID, REGION.
+            mpu.rbar.write(region.base as u32 | 1 << 4 | number);
+            mpu.rasr.write(ap | Self::NORMAL_MEMORY | size | 1);
+        }
+    }
+}
+
+#[cfg(feature = "has-pmsav7")]
+impl<const S: usize> TaskIsolation for CortexMMpu<S> {
+    fn enter(&self, task: usize, storage: Region) {
+        // Safety: we own the MPU, per the type's docs, and disable it while
+        // reprogramming its regions, in a critical section so that an
+        // interrupt executor can't start reprogramming it too.
+        critical_section(|| unsafe {
+            let mpu = &*cortex_m::peripheral::MPU::PTR;
+            // MPU_TYPE.DREGION: how many regions there are, or 0 if there's
+            // no MPU at all. We need at least two.
+            let regions = (mpu._type.read() >> 8) & 0xFF;
+            crate::cheap_assert!(regions >= 2);
+            mpu.ctrl.write(0);
+            Self::set_region(0, self.protected, Self::AP_NONE);
+            // Higher-numbered regions take priority where they overlap, so
+            // these punch holes in the protected region.
+            Self::set_region(1, Self::covering(storage), Self::AP_RW);
+            let mut number = 2;
+            self.grants.for_task(task, |region| {
+                crate::cheap_assert!(number < regions);
+                Self::set_region(number, region, Self::AP_RW);
+                number += 1;
+            });
+            for n in number..regions {
+                mpu.rnr.write(n);
+                mpu.rasr.write(0);
+            }
+            mpu.ctrl.write(Self::CTRL_ENABLE);
+            cortex_m::asm::dsb();
+            cortex_m::asm::isb();
+        });
+    }
+
+    fn leave(&self, _task: usize) {
+        // Safety: we own the MPU, per the type's docs.
+        unsafe {
+            (*cortex_m::peripheral::MPU::PTR).ctrl.write(0);
+        }
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    }
+}
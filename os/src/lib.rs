@@ -1,7 +1,8 @@
 //! A simple but powerful `async` RTOS based around Rust `Future`s.
 //!
 //! This provides a lightweight operating environment for running async Rust
-//! code on ARM Cortex-M microprocessors, plus some useful doodads and gizmos.
+//! code on ARM Cortex-M microprocessors and single-core RISC-V
+//! microcontrollers, plus some useful doodads and gizmos.
 //!
 //! `lilos` is deliberately designed to be compact, to avoid the use of proc
 //! macros, to be highly portable to different microcontrollers, and to be as
@@ -48,10 +49,17 @@
 //! enabling/disabling portions of the system:
 //!
 //! - `systick` (on by default). Enables reliance on the ARM M-profile SysTick
-//! timer for portable timekeeping. Disabling makes the executor smaller at the
-//! cost of losing all `time` API. On platforms where the SysTick timer stops
-//! during sleep, such as Nordic nRF52, you may want to disable this feature and
-//! use a different timekeeping mechanism.
+//! timer for portable timekeeping. Implies `time`. On platforms where the
+//! SysTick timer stops during sleep, such as Nordic nRF52, you may want to
+//! disable this feature and supply a different timer through `time`. On
+//! RISC-V, this feature uses the standard machine timer instead of SysTick.
+//!
+//! - `time` (on by default, through `systick`). Enables the
+//! [`time`][crate::time] module, the [`watchdog`][crate::watchdog] module
+//! built on it, and the executor's support for timers, on top
+//! of SysTick or of any timer you provide as a
+//! [`TimeSource`][crate::time::TimeSource]. Disabling makes the executor
+//! smaller at the cost of losing all `time` API.
 //!
 //! - `mutex` (on by default). Enables access to the [`mutex`][crate::mutex]
 //! module for blocking access to shared data. Leaving this feature enabled has
@@ -66,6 +74,68 @@
 //! rendezvous. `handoff` contains some API that is not strictly cancel-safe, so
 //! you need to request it explicitly.
 //!
+//! - `hosted` (**off** by default). Builds the OS for a normal `std` target,
+//! like x86_64 Linux, instead of a microcontroller. The executor runs on the
+//! thread that calls `run_tasks`, idling by parking that thread, and other
+//! threads can wake tasks the way ISRs would on real hardware. The `time`
+//! module uses the host's monotonic clock in place of SysTick. This is intended
+//! for testing application logic under `cargo test` without a board.
+//!
+//! - `virtual-time` (**off** by default). Lets tests stop the clock and move it
+//! forward by hand, or skip straight to the next timer deadline, so that
+//! timeout logic can be tested quickly and deterministically. See the
+//! [`time`][crate::time] module for details. Implies `systick`.
+//!
+//! - `wake-bits-64`, `wake-bits-128`, `wake-bits-256` (**off** by default).
+//! Make the executor track task wakeups precisely for up to 64, 128, or 256
+//! tasks, instead of one machine word's worth (32 on Cortex-M). Tasks beyond
+//! the limit still work, but may be woken spuriously. This costs a few words of
+//! RAM per [`Notify`][exec::Notify]. If more than one is enabled, the largest
+//! wins. See [`WakeMask`][exec::WakeMask].
+//!
+//! - `stats` (**off** by default). Lets the executor record per-task poll
+//! counts and poll times, for finding out which task is using the CPU. See
+//! [`exec::enable_task_stats`].
+//!
+//! - `trace` (**off** by default). Lets the executor report scheduling events
+//! (polls, wakeups, idle, timers) to a sink of your choosing, such as an
+//! in-RAM ring buffer. See [`exec::set_trace_sink`].
+//!
+//! - `foreign-wakers` (**off** by default). Lets each [`Notify`][exec::Notify]
+//! hold on to a `Waker` that didn't come from `lilos` (from a nested executor,
+//! say) and wake it properly, instead of waking it immediately. See the
+//! `Notify` docs for details.
+//!
+//! - `interrupt-executor` (**off** by default). Lets you run some tasks on a
+//! second executor inside an interrupt handler, where they can preempt the
+//! rest. See [`exec::InterruptExecutor`].
+//!
+//! - `tickless` (**off** by default). Stops SysTick from interrupting every
+//! tick while the executor is idle, instead waking the processor only when the
+//! next timer is due. See the [`time`][crate::time] module for details.
+//! Implies `systick`.
+//!
+//! - `task-control` (**off** by default). Lets you suspend, resume, stop, and
+//! start tasks by index at runtime, for switching between operating modes. See
+//! [`exec::suspend_task`].
+//!
+//! - `poll-budget` (**off** by default). Lets you set a limit on how long any
+//! one task poll should take, and have the executor call a hook when a task
+//! exceeds it. See [`exec::set_poll_budget`].
+//!
+//! - `time-slice` (**off** by default). Adds [`exec::maybe_yield`], which
+//! yields only if the current task has been running for longer than a slice
+//! you choose, for breaking up long computations cheaply.
+//!
+//! - `task-isolation` (**off** by default). Lets the executor reconfigure the
+//! MPU around each task poll, so that each task can only touch its own memory
+//! and what you've shared with it. See the [`isolation`] module.
+//!
+//! - `tick-hz-100`, `tick-hz-10000`, `tick-hz-32768`, `tick-hz-1000000`
+//! (**off** by default). Change the tick rate from its default of 1 kHz. If
+//! more than one is enabled, the fastest wins. See
+//! [`time::TICK_HZ`][crate::time::TICK_HZ]. Implies `time`.
+//!
 //!
 //! # Composition and dynamic behavior
 //!
@@ -75,6 +145,13 @@
 //!
 //! For instance, a task can fork into several concurrent routines using macros
 //! like [`select_biased!`] or [`join!`] from the `futures` crate.
+//! The [`branch`] module provides equivalents that give each concurrent
+//! routine its own `Waker`, so that only the routines that were actually woken
+//! get polled.
+//!
+//! A task can also start new routines as it goes, up to a fixed limit, using
+//! a [`TaskPool`][pool::TaskPool] -- to handle each incoming network
+//! connection, say.
 //!
 //! # Concurrency and interrupts
 //!
@@ -153,6 +230,9 @@
     unused_qualifications,
 )]
 
+#[cfg(feature = "hosted")]
+extern crate std;
+
 /// Internal assert macro that doesn't stringify its expression or generate any
 /// fancy messages. This means failures must be diagnosed by file:line only, so,
 /// don't use this more than once on the same line. In exchange, this makes
@@ -165,17 +245,25 @@ macro_rules! cheap_assert {
 #[allow(unused_imports)]
 pub(crate) use cheap_assert;
 
+mod arch;
+
 #[macro_use]
 pub mod list;
 pub mod exec;
+pub mod branch;
+pub mod pool;
 pub mod util;
 pub mod atomic;
 
-#[cfg(feature = "systick")]
+#[cfg(feature = "time")]
 pub mod time;
+#[cfg(feature = "time")]
+pub mod watchdog;
 #[cfg(feature = "mutex")]
 pub mod mutex;
 #[cfg(feature = "spsc")]
 pub mod spsc;
 #[cfg(feature = "handoff")]
 pub mod handoff;
+#[cfg(feature = "task-isolation")]
+pub mod isolation;
@@ -349,6 +349,24 @@ impl<T> List<T> {
     fn root_mut(self: Pin<&mut Self>) -> Pin<&mut Node<T>> {
         unsafe { Pin::new_unchecked(&mut Pin::get_unchecked_mut(self).root) }
     }
+
+    /// Returns a copy of the `contents` of the node at the head of the list,
+    /// or `None` if the list is empty.
+    ///
+    /// In a sorted list, this is the smallest value -- for a list of
+    /// timestamps, the next one due.
+    pub fn first(self: Pin<&Self>) -> Option<T>
+        where T: Clone,
+    {
+        let candidate = self.root.next.get();
+        if candidate != NonNull::from(&self.root) {
+            // Safety: Link Valid Invariant
+            let cref = unsafe { candidate.as_ref() };
+            Some(cref.contents.clone())
+        } else {
+            None
+        }
+    }
 }
 
 impl<T: PartialOrd> List<T> {
@@ -0,0 +1,194 @@
+//! A fixed-size pool of futures that a task can start at runtime.
+//!
+//! The set of tasks passed to [`run_tasks`][crate::exec::run_tasks] is fixed
+//! when the executor starts, which means every concurrent activity has to be
+//! declared up front. That's a problem for things like network servers, which
+//! want to start a handler for each connection as it arrives. A [`TaskPool`]
+//! solves this by giving one task room for up to `N` futures of the same type,
+//! which it can start and finish while it runs:
+//!
+//! ```ignore
+//! async fn server(listener: &Listener) -> Infallible {
+//!     let mut handlers = pin!(TaskPool::<_, 4>::new());
+//!     loop {
+//!         match select(listener.accept(), handlers.as_mut().next_completion())
+//!             .await
+//!         {
+//!             Either::Left(conn) => {
+//!                 if handlers.as_mut().spawn(handle_connection(conn)).is_err() {
+//!                     // Full; drop the connection on the floor.
+//!                 }
+//!             }
+//!             Either::Right((slot, result)) => {
+//!                 log_result(slot, result);
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! The futures live inside the `TaskPool` itself, so there's no heap
+//! involved: the pool is as big as `N` of them. Because they all have the
+//! same type, they're usually produced by calling the same `async fn`.
+//!
+//! # Wakeups
+//!
+//! Each future in the pool gets its own `Waker`, and only the futures whose
+//! `Waker`s have fired get polled -- using the same mechanism as the
+//! [`branch`][crate::branch] combinators, so the caveats in its docs apply
+//! here too. In particular, a pool with any futures in it takes up one of the
+//! slots described there, and a future waiting on a `Notify` gets polled
+//! whenever the pool is, unless the `foreign-wakers` feature is on and the
+//! `Notify` has room for its `Waker`.
+//!
+//! Wakeups are remembered between calls to [`TaskPool::next_completion`], so
+//! it's fine to drop the future it returns (say, because something else in a
+//! `select` won) and call it again later.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use crate::branch::{Tracker, MAX_BRANCHES};
+
+/// A pool of up to `N` futures of type `F`, which are run by whatever task
+/// drives the pool. See the [module docs](self) for more.
+///
+/// `N` can be at most the number of bits in a `usize`.
+#[derive(Debug)]
+pub struct TaskPool<F, const N: usize> {
+    tracker: Tracker,
+    /// Bitmask of slots that need polling regardless of their wakers:
+    /// futures that were just spawned, and futures that were woken but hadn't
+    /// been polled when another one finished.
+    needs_poll: usize,
+    slots: [Option<F>; N],
+}
+
+impl<F: Future, const N: usize> TaskPool<F, N> {
+    /// Evaluated in `new` to reject pools that are too big to track.
+    const FITS: () = assert!(N <= MAX_BRANCHES, "TaskPool is too big");
+
+    /// Creates an empty pool.
+    pub fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::FITS;
+        Self {
+            tracker: Tracker::default(),
+            needs_poll: 0,
+            slots: core::array::from_fn(|_| None),
+        }
+    }
+
+    /// Adds `future` to the pool, returning the index of the slot it went
+    /// into. It will first be polled the next time the pool is.
+    ///
+    /// If the pool is full, gives `future` back instead.
+    pub fn spawn(self: Pin<&mut Self>, future: F) -> Result<usize, F> {
+        // Safety: we only put a new future in an empty slot, and don't move
+        // anything that's already there.
+        let this = unsafe { self.get_unchecked_mut() };
+        match this.slots.iter().position(Option::is_none) {
+            Some(i) => {
+                this.slots[i] = Some(future);
+                this.needs_poll |= 1 << i;
+                Ok(i)
+            }
+            None => Err(future),
+        }
+    }
+
+    /// Drops the future in slot `index`, if there is one, freeing the slot.
+    /// Returns `true` if there was a future to drop.
+    pub fn cancel(self: Pin<&mut Self>, index: usize) -> bool {
+        // Safety: assigning to the slot drops its future in place.
+        let this = unsafe { self.get_unchecked_mut() };
+        match this.slots.get_mut(index) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the number of futures in the pool.
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_some()).count()
+    }
+
+    /// Checks whether the pool has no futures in it.
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(Option::is_none)
+    }
+
+    /// Checks whether the pool has no room for more futures.
+    pub fn is_full(&self) -> bool {
+        self.slots.iter().all(Option::is_some)
+    }
+
+    /// Returns a future that runs the futures in the pool until one of them
+    /// finishes, and then resolves to its slot index and its output. The slot
+    /// is free again by then.
+    ///
+    /// If the pool is empty, the returned future never resolves; it's meant
+    /// to be used alongside something that leads to more futures being
+    /// spawned, as shown in the [module docs](self).
+    pub fn next_completion(self: Pin<&mut Self>) -> NextCompletion<'_, F, N> {
+        NextCompletion { pool: self }
+    }
+
+    fn poll_completion(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<(usize, F::Output)> {
+        // Safety: the futures in `slots` are only ever polled in place, and
+        // dropped in place by assigning to their slot.
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.is_empty() {
+            // Don't hold on to a branch slot while we've got nothing to wake.
+            this.tracker.release();
+            return Poll::Pending;
+        }
+        let ready = this.tracker.begin_poll(cx) | core::mem::take(&mut this.needs_poll);
+        for (i, slot) in this.slots.iter_mut().enumerate() {
+            if ready & (1 << i) == 0 {
+                continue;
+            }
+            if let Some(future) = slot {
+                let waker = this.tracker.waker(i, cx);
+                // Safety: see above.
+                let future = unsafe { Pin::new_unchecked(future) };
+                if let Poll::Ready(output) = future.poll(&mut Context::from_waker(&waker)) {
+                    *slot = None;
+                    // Remember any later slots that were ready, since we're
+                    // not going to get to them this time.
+                    this.needs_poll |= ready & (!1 << i);
+                    return Poll::Ready((i, output));
+                }
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<F: Future, const N: usize> Default for TaskPool<F, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by [`TaskPool::next_completion`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct NextCompletion<'a, F, const N: usize> {
+    pool: Pin<&'a mut TaskPool<F, N>>,
+}
+
+impl<F: Future, const N: usize> Future for NextCompletion<'_, F, N> {
+    type Output = (usize, F::Output);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.pool.as_mut().poll_completion(cx)
+    }
+}
@@ -1,16 +1,18 @@
-//! Timekeeping using the SysTick Timer.
+//! Timekeeping using the SysTick Timer, or a timer of your choosing.
 //!
-//! **Note:** this entire module is only available if the `systick` feature is
-//! present; it is on by default.
+//! **Note:** this entire module is only available if the `time` feature is
+//! present; it is on by default, because the `systick` feature implies it.
 //!
-//! The OS uses the Cortex-M SysTick Timer to maintain a monotonic counter
-//! recording the number of milliseconds ("ticks") since boot. This module
-//! provides ways to read that timer, and also to arrange for tasks to be woken
-//! at specific times (such as [`sleep_until`] and [`sleep_for`]).
+//! The OS maintains a monotonic counter recording the number of "ticks" --
+//! milliseconds, unless you say otherwise -- since boot. By default, it uses
+//! the Cortex-M SysTick Timer to do this. This module provides ways to read
+//! that counter, and also to arrange for tasks to be woken at specific times
+//! (such as [`sleep_until`] and [`sleep_for`]).
 //!
-//! To use this facility in an application, you need to call
-//! [`initialize_sys_tick`] to inform the OS of the system clock speed.
-//! Otherwise, no operations in this module will work properly.
+//! To use this facility with SysTick, you need to call [`initialize_sys_tick`]
+//! to inform the OS of the system clock speed. Otherwise, no operations in
+//! this module will work properly. To use some other timer instead, see
+//! [Using a different timer](#using-a-different-timer).
 //!
 //! You can get the value of tick counter using [`TickTime::now`].
 //!
@@ -21,13 +23,14 @@
 //!
 //! `TickTime` represents a specific point in time, measured as a number of
 //! ticks since boot (or, really, since the executor was started). It's a
-//! 64-bit count of milliseconds, which means it overflows every 584 million
-//! years. This lets us ignore overflows in timestamps, making everything
-//! simpler. `TickTime` is analogous to `std::time::Instant` from the Rust
-//! standard library.
+//! 64-bit count of ticks, which at the default rate of one tick per
+//! millisecond means it overflows every 584 million years. This lets us ignore
+//! overflows in timestamps, making everything simpler. `TickTime` is analogous
+//! to `std::time::Instant` from the Rust standard library.
 //!
-//! `Millis` represents a relative time interval in milliseconds. This uses the
-//! same representation as `TickTime`, so adding them together is cheap.
+//! `Millis` represents a relative time interval in milliseconds, and `Micros`
+//! in microseconds. At the default tick rate, `Millis` uses the same
+//! representation as `TickTime`, so adding them together is cheap.
 //!
 //! `core::time::Duration` is similar to `Millis` but with a lot more bells and
 //! whistles. It's the type used to measure time intervals in the Rust standard
@@ -51,7 +54,7 @@
 //! # Fixing "lost ticks"
 //!
 //! If the longest sequence in your application between any two `await` points
-//! takes less than a millisecond, the standard timer configuration will work
+//! takes less than a tick, the standard timer configuration will work
 //! fine and keep reliable time.
 //!
 //! However, if you sometimes need to do more work than that -- or if you're
@@ -60,50 +63,499 @@
 //! For more information, see
 //! [`run_tasks_with_preemption`][crate::exec::run_tasks_with_preemption].
 //!
-//! # Getting higher precision
+//! # Using a different timer
 //!
-//! For many applications, milliseconds are a fine unit of time, but sometimes
-//! you need something more precise. Currently, the easiest way to do this is to
-//! enlist a different hardware timer. The `time` module has no special
-//! privileges that you can't make use of, and adding your own alternate
-//! timekeeping module is explictly supported in the design (this is why the
-//! `"systick"` feature exists).
+//! SysTick isn't always the right timer to use. On processors like the Nordic
+//! nRF52 series, the best sleep mode to use when idling the CPU also stops
+//! SysTick, so it isn't useful as a monotonic clock; you'll want to use an RTC
+//! or some other vendor-specific low-power timer instead.
 //!
-//! This can also be useful on processors like the Nordic nRF52 series, where
-//! the best sleep mode to use when idling the CPU also stops the systick timer.
-//! On platforms like that, the systick isn't useful as a monotonic clock, and
-//! you'll want to use some other vendor-specific timer.
+//! To do this, implement the [`TimeSource`] trait for your timer, and install
+//! it with [`set_time_source`] before starting the executor. Everything in this
+//! module, and the executor's handling of timers, then works on top of your
+//! timer instead of SysTick. Your timer's interrupt handler should call
+//! [`alarm_fired`].
 //!
-//! Currently there's no example of how to do this in the repo. If you need
-//! this, please file an issue.
+//! If you do this, you'll probably want to turn off the `systick` feature
+//! (which claims the SysTick interrupt handler) and turn on `time` by itself.
+//! Without `systick`, the OS has no clock of its own, and using this module
+//! without installing a `TimeSource` will panic.
+//!
+//! # Changing the tick rate
+//!
+//! By default, a tick is one millisecond. For many applications, milliseconds
+//! are a fine unit of time, but sometimes you need something more precise --
+//! or, on a low-power board, something coarser, or something that divides a
+//! 32.768 kHz crystal evenly. The `tick-hz-100`, `tick-hz-10000`,
+//! `tick-hz-32768`, and `tick-hz-1000000` features change the tick rate to
+//! the given number of ticks per second, which is available to your code as
+//! [`TICK_HZ`]. (If more than one is enabled, the fastest wins.)
+//!
+//! Everything in this module still takes its intervals in [`Millis`],
+//! [`Micros`], or `Duration`, and converts them to ticks. Intervals that don't
+//! come out to a whole number of ticks are rounded _up_ when added to a
+//! `TickTime`, so that a sleep is never shorter than you asked for; measuring
+//! an interval with (say) [`TickTime::millis_since`] rounds _down_. At the
+//! default rate, converting `Millis` is free. At other rates, it costs a
+//! multiply or divide by a constant.
+//!
+//! With SysTick, every tick is an interrupt, so faster ticks cost more CPU
+//! time; 10 kHz is reasonable on most parts, but 1 MHz is really only useful
+//! with a custom [`TimeSource`]. SysTick can only count whole CPU cycles per
+//! tick, so if the CPU clock isn't a multiple of the tick rate, the clock will
+//! run slightly fast.
+//!
+//! # Tickless idle
+//!
+//! Normally, SysTick interrupts the processor every tick, even if nothing is
+//! waiting for that tick. This keeps a sleeping processor waking up (by
+//! default) a thousand times a second, which matters if you're running from a
+//! battery. With the `tickless` feature, the executor instead looks at the
+//! earliest deadline in the timer list just before it idles, and reprograms
+//! SysTick to fire then (or as close to then as the 24-bit SysTick counter can
+//! reach: about a second at 16 MHz, or 100 ms at 168 MHz). If an interrupt
+//! wakes the processor before then, the executor works out how much time has
+//! passed and corrects the tick counter before polling any tasks, so task code
+//! sees the same time it would
+//! without the feature.
+//!
+//! This has a few costs:
+//!
+//! - Once you've called [`initialize_sys_tick`], the OS owns SysTick outright.
+//!   Don't change its settings afterwards.
+//! - ISRs that call [`TickTime::now`] while the processor is idling will see
+//!   the time at which idle began.
+//! - Reprogramming SysTick loses a few cycles each time, so the tick counter
+//!   can drift slightly behind the CPU clock if the processor idles very
+//!   often.
+//!
+//! In hosted builds, `tickless` makes the executor thread park until the next
+//! deadline, rather than waking up every tick.
+//!
+//! This only affects SysTick. A custom [`TimeSource`] gets the same
+//! information through [`TimeSource::set_alarm`], whether or not `tickless` is
+//! enabled.
+//!
+//! # RISC-V
+//!
+//! RISC-V parts don't have SysTick. On RISC-V targets, the `systick` feature
+//! instead uses the machine timer in the core-local interruptor (CLINT), which
+//! is the closest thing the architecture has to a standard timer. Call
+//! `initialize_machine_timer` with the CLINT's address and the rate its timer
+//! counts at, instead of `initialize_sys_tick`; the OS then owns hart 0's
+//! `mtimecmp` register and the `MachineTimer` interrupt handler.
+//!
+//! The machine timer's compare register is 64 bits wide, so with `tickless`,
+//! the processor can idle right up to the next deadline, however far away it
+//! is.
+//!
+//! # Hosted builds
+//!
+//! With the `hosted` feature, there is no SysTick. Instead, the tick counter
+//! follows the host's monotonic clock (`std::time::Instant`), counting
+//! milliseconds since the executor started. It advances at the point in the
+//! executor loop where the SysTick ISR would get a chance to run on real
+//! hardware, and like that ISR, it advances one tick at a time: if the host
+//! thread falls behind, the executor runs extra passes, without idling, until
+//! the counter catches up. This means every tick is seen by the executor, just
+//! as on hardware. As on hardware with interrupts masked, time does not appear
+//! to pass during any single poll of a task. There's no need to call
+//! `initialize_sys_tick` (and, in fact, it doesn't exist).
+//!
+//! # Virtual time
+//!
+//! Testing code that uses timeouts can be slow, because the test has to wait
+//! for the timeouts to actually happen. With the `virtual-time` feature, a test
+//! can instead call [`start_virtual_time`] to stop the clock, and then move it
+//! forward by hand using [`advance_virtual_time`] -- or jump straight to the
+//! next time some task is waiting for, using [`advance_to_next_deadline`]. An
+//! hour-long timeout can then be tested in a few microseconds, and the result
+//! doesn't depend on how fast the machine happens to be.
+//!
+//! While virtual time is in effect, time _only_ passes when you advance it.
+//! This means code that busy-waits for time to pass (for example, by calling
+//! `yield_cpu` in a loop until a deadline) will wait forever unless some other
+//! task is advancing the clock.
+//!
+//! [`stop_virtual_time`] puts the clock back under the control of the timer
+//! hardware (or the host clock, in hosted builds). The clock picks up where
+//! the virtual clock left off, so time never appears to go backwards.
 
 use core::future::Future;
 use core::ops::{Add, AddAssign};
 use core::pin::Pin;
-use core::sync::atomic::{AtomicU32, Ordering};
 use core::task::{Context, Poll};
 use core::time::Duration;
 
-use cortex_m::peripheral::{syst::SystClkSource, SYST};
-use cortex_m_rt::exception;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use pin_project_lite::pin_project;
 
-use crate::atomic::AtomicArithExt;
+#[cfg(feature = "tickless")]
+mod tickless;
+#[cfg(all(feature = "tickless", feature = "hosted"))]
+pub(crate) use tickless::host_park_timeout;
+
+#[cfg(all(feature = "systick", feature = "has-mtimer"))]
+mod mtimer;
+#[cfg(all(feature = "systick", feature = "has-mtimer"))]
+pub use mtimer::initialize_machine_timer;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "hosted")] {
+        use core::sync::atomic::AtomicU64;
+        use std::sync::OnceLock;
+        use std::time::Instant;
+    } else if #[cfg(feature = "systick")] {
+        use core::sync::atomic::AtomicU32;
+
+        #[cfg(feature = "has-systick")]
+        use cortex_m::peripheral::{syst::SystClkSource, SYST};
+        #[cfg(feature = "has-systick")]
+        use cortex_m_rt::exception;
+
+        use crate::atomic::AtomicArithExt;
+    }
+}
+
+/// Number of ticks per second. This is 1000 by default; see [Changing the tick
+/// rate](self#changing-the-tick-rate).
+pub const TICK_HZ: u64 = if cfg!(feature = "tick-hz-1000000") {
+    1_000_000
+} else if cfg!(feature = "tick-hz-32768") {
+    32_768
+} else if cfg!(feature = "tick-hz-10000") {
+    10_000
+} else if cfg!(feature = "tick-hz-100") {
+    100
+} else {
+    1_000
+};
 
 /// Bottom 32 bits of the tick counter. Updated by ISR.
+#[cfg(all(feature = "systick", not(feature = "hosted")))]
 static TICK: AtomicU32 = AtomicU32::new(0);
 /// Top 32 bits of the tick counter. Updated by ISR.
+#[cfg(all(feature = "systick", not(feature = "hosted")))]
 static EPOCH: AtomicU32 = AtomicU32::new(0);
 
-/// Sets up the tick counter for 1kHz operation, assuming a CPU core clock of
-/// `clock_mhz`.
+/// Tick counter for hosted builds. Updated by `catch_up_host_clock`.
+#[cfg(feature = "hosted")]
+static HOST_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// Amount added to the host clock reading to produce `HOST_TICK`. This is
+/// nonzero only after virtual time has been used, which leaves the tick
+/// counter ahead of the host clock. Applied with wrapping arithmetic.
+#[cfg(feature = "hosted")]
+static HOST_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Set while virtual time is in effect. The usual clock source leaves the tick
+/// counter alone when this is set.
+#[cfg(feature = "virtual-time")]
+static VIRTUAL: AtomicBool = AtomicBool::new(false);
+
+/// Reads the host's monotonic clock in ticks.
+#[cfg(feature = "hosted")]
+fn host_clock_ticks() -> u64 {
+    static BOOT: OnceLock<Instant> = OnceLock::new();
+    let d = BOOT.get_or_init(Instant::now).elapsed();
+    // Unlike `duration_to_ticks`, this rounds down, since a tick hasn't
+    // happened until it's over.
+    d.as_secs() * TICK_HZ
+        + u64::from(d.subsec_nanos()) * TICK_HZ / 1_000_000_000
+}
+
+/// Returns the number of ticks by which the hosted tick counter trails the
+/// host's monotonic clock.
+#[cfg(feature = "hosted")]
+fn host_clock_lag() -> u64 {
+    #[cfg(feature = "virtual-time")]
+    if VIRTUAL.load(Ordering::Acquire) {
+        return 0;
+    }
+
+    let t = host_clock_ticks()
+        .wrapping_add(HOST_OFFSET.load(Ordering::Relaxed));
+    let lag = t.wrapping_sub(HOST_TICK.load(Ordering::Acquire));
+    // The counter can briefly be ahead of the host clock, if a virtual time
+    // session is ended between reads; treat that as no lag.
+    if (lag as i64) < 0 { 0 } else { lag }
+}
+
+/// Advances the hosted tick counter toward the host's monotonic clock. This
+/// stands in for the SysTick ISR, and is called by the executor whenever that
+/// ISR would have had a chance to run.
+///
+/// Like the ISR, this only ever advances the counter by one tick at a time, so
+/// that the executor gets to process timers at every tick even if the host
+/// thread falls behind. (See `host_clock_behind`.)
+#[cfg(feature = "hosted")]
+pub(crate) fn catch_up_host_clock() {
+    if host_clock_lag() != 0 {
+        HOST_TICK.fetch_add(1, Ordering::Release);
+        #[cfg(feature = "interrupt-executor")]
+        crate::exec::pend_timer_executors();
+    }
+}
+
+/// Checks whether the hosted tick counter has ticks left to catch up on. The
+/// executor doesn't idle while this is true, which is the equivalent of
+/// SysTick being pended on real hardware.
+#[cfg(feature = "hosted")]
+pub(crate) fn host_clock_behind() -> bool {
+    host_clock_lag() != 0
+}
+
+/// Adds `ticks` to the tick counter. Called from the timer ISR, or with
+/// interrupts masked.
+#[cfg(all(feature = "systick", not(feature = "hosted")))]
+fn advance_tick_counter(ticks: u32) {
+    let old = TICK.fetch_add_polyfill(ticks, Ordering::Release);
+    if old.checked_add(ticks).is_none() {
+        EPOCH.fetch_add_polyfill(1, Ordering::Release);
+    }
+}
+
+/// Overwrites the tick counter. Used by virtual time, which is careful to only
+/// move it forward.
+#[cfg(all(feature = "virtual-time", feature = "hosted"))]
+fn set_tick_counter(t: TickTime) {
+    HOST_TICK.store(t.0, Ordering::Release);
+}
+
+/// Overwrites the tick counter. Used by virtual time, which is careful to only
+/// move it forward.
+#[cfg(all(feature = "virtual-time", not(feature = "hosted")))]
+fn set_tick_counter(t: TickTime) {
+    // Mask interrupts so that an ISR reading the time can't see a torn value.
+    // (The SysTick ISR won't touch the counter in virtual time.)
+    crate::arch::with_interrupts_masked(|| {
+        EPOCH.store((t.0 >> 32) as u32, Ordering::SeqCst);
+        TICK.store(t.0 as u32, Ordering::SeqCst);
+    });
+}
+
+/// Switches the clock over to virtual time. See the module docs on [virtual
+/// time](self#virtual-time) for more.
+///
+/// The clock stops at its current reading, and only moves when you call
+/// [`advance_virtual_time`] or [`advance_to_next_deadline`].
+///
+/// Virtual time takes over the OS's own clock, so it can't be used along with
+/// a custom [`TimeSource`].
+///
+/// # Panics
+///
+/// If virtual time is already in effect, or if a `TimeSource` has been
+/// installed.
+#[cfg(feature = "virtual-time")]
+pub fn start_virtual_time() {
+    use crate::atomic::AtomicExt;
+
+    crate::cheap_assert!(!SOURCE_SET.load(Ordering::Acquire));
+
+    let was_virtual = VIRTUAL.swap_polyfill(true, Ordering::AcqRel);
+    crate::cheap_assert!(!was_virtual);
+}
+
+/// Returns the clock to the control of the hardware timer (or, for hosted
+/// builds, the host clock). The clock keeps counting up from where virtual time
+/// left it.
+///
+/// # Panics
+///
+/// If virtual time isn't in effect.
+#[cfg(feature = "virtual-time")]
+pub fn stop_virtual_time() {
+    crate::cheap_assert!(VIRTUAL.load(Ordering::Acquire));
+    #[cfg(feature = "hosted")]
+    {
+        // Virtual time has likely moved us ahead of the host clock. Arrange
+        // for the host clock to pick up from here.
+        let offset = HOST_TICK.load(Ordering::Acquire)
+            .wrapping_sub(host_clock_ticks());
+        HOST_OFFSET.store(offset, Ordering::Relaxed);
+    }
+    VIRTUAL.store(false, Ordering::Release);
+}
+
+/// Moves virtual time forward by `d`, and wakes any tasks whose timers have
+/// come due.
+///
+/// `d` can be any type that can be added to a `TickTime`, which in practice
+/// means [`Millis`], [`Micros`], or [`Duration`].
+///
+/// # Preconditions
+///
+/// This can only be used within a task, while virtual time is in effect.
+#[cfg(feature = "virtual-time")]
+pub fn advance_virtual_time<D>(d: D)
+    where TickTime: Add<D, Output = TickTime>,
+{
+    advance_virtual_time_to(TickTime::now() + d);
+}
+
+/// Moves virtual time forward to the earliest time that any task is sleeping
+/// until, and wakes the task(s) waiting for it. Returns the new time, or `None`
+/// if no tasks are waiting on timers (in which case the clock doesn't move).
+///
+/// If a timer is already due, this wakes its tasks without moving the clock.
+///
+/// This is handy in idle hooks, to make a simulation run as fast as possible:
+/// whenever all tasks are blocked, skip ahead to the next thing that will
+/// happen.
+///
+/// # Preconditions
+///
+/// This can only be used within a task (or the executor's idle hook), while
+/// virtual time is in effect.
+#[cfg(feature = "virtual-time")]
+pub fn advance_to_next_deadline() -> Option<TickTime> {
+    let next = crate::exec::with_timer_list(|tl| tl.first())?;
+    let next = next.max(TickTime::now());
+    advance_virtual_time_to(next);
+    Some(next)
+}
+
+#[cfg(feature = "virtual-time")]
+fn advance_virtual_time_to(t: TickTime) {
+    crate::cheap_assert!(VIRTUAL.load(Ordering::Acquire));
+    // Time doesn't go backwards, even virtual time.
+    crate::cheap_assert!(t >= TickTime::now());
+    set_tick_counter(t);
+    // Wake things now, rather than waiting for the executor to notice. This
+    // ensures that the executor won't go idle waiting for an interrupt that
+    // isn't coming.
+    crate::exec::wake_expired_timers(t);
+    #[cfg(feature = "interrupt-executor")]
+    crate::exec::pend_timer_executors();
+}
+
+/// A monotonic clock, with an alarm, that the OS can use in place of SysTick.
+/// See [Using a different timer](self#using-a-different-timer) for the
+/// overview.
+///
+/// Both methods can be called from any context, including ISRs, and should be
+/// quick.
+pub trait TimeSource: Sync {
+    /// Returns the current time, in ticks since boot. There are [`TICK_HZ`]
+    /// ticks in a second.
+    ///
+    /// This must never go backwards. It should start near zero, and must
+    /// continue from wherever the OS's own clock was if you install the source
+    /// after using the clock. (The easiest way to manage this is to install it
+    /// before starting the executor.)
+    fn now(&self) -> TickTime;
+
+    /// Asks for an interrupt at `deadline`, replacing any alarm requested
+    /// earlier, or cancels the alarm if `deadline` is `None`.
+    ///
+    /// The executor calls this just before it idles, with the earliest time a
+    /// task is waiting for, so that the processor can sleep until then. An
+    /// interrupt that arrives early is harmless, but one that arrives late
+    /// makes timers late.
+    ///
+    /// The default implementation does nothing, which is correct for sources
+    /// that generate an interrupt on every tick anyway.
+    fn set_alarm(&self, deadline: Option<TickTime>) {
+        let _ = deadline;
+    }
+}
+
+/// The installed time source. Only read once `SOURCE_SET` has been observed to
+/// be true.
+static mut SOURCE: Option<&'static dyn TimeSource> = None;
+/// Set, once, after `SOURCE` has been written.
+static SOURCE_SET: AtomicBool = AtomicBool::new(false);
+
+/// Installs `source` as the OS's clock, from now on. See [Using a different
+/// timer](self#using-a-different-timer).
+///
+/// This is normally called before starting the executor.
+///
+/// # Panics
+///
+/// If a source has already been installed, if virtual time is in effect, or
+/// if called from an ISR.
+pub fn set_time_source(source: &'static dyn TimeSource) {
+    crate::cheap_assert!(!crate::arch::in_isr());
+    crate::cheap_assert!(!SOURCE_SET.load(Ordering::Relaxed));
+    #[cfg(feature = "virtual-time")]
+    crate::cheap_assert!(!VIRTUAL.load(Ordering::Acquire));
+    // Safety: nobody reads SOURCE until SOURCE_SET is true, and we're the only
+    // writer, since we've just checked that SOURCE_SET is false (and ISRs
+    // can't get here).
+    unsafe {
+        SOURCE = Some(source);
+    }
+    SOURCE_SET.store(true, Ordering::Release);
+}
+
+/// Returns the installed time source, if any.
+#[inline(always)]
+fn time_source() -> Option<&'static dyn TimeSource> {
+    if SOURCE_SET.load(Ordering::Acquire) {
+        // Safety: SOURCE is never written again once SOURCE_SET is true.
+        unsafe { SOURCE }
+    } else {
+        None
+    }
+}
+
+/// Tells the OS that a [`TimeSource`]'s alarm has gone off. Call this from the
+/// source's interrupt handler, each time it fires.
+///
+/// The executor will check its timers when the interrupt returns, whether or
+/// not you call this. What this does is pend any
+/// [`InterruptExecutor`][crate::exec::InterruptExecutor]s that have tasks
+/// waiting on timers, since they can't notice the interrupt on their own. (The
+/// executor keeps the alarm no more than a tick away while they're waiting, but
+/// only while it's idle, so their timers may run late if the source doesn't
+/// interrupt every tick and other tasks keep the processor busy.)
+pub fn alarm_fired() {
+    #[cfg(feature = "interrupt-executor")]
+    crate::exec::pend_timer_executors();
+}
+
+/// Called by the executor just before it idles. `deadline` produces the
+/// earliest time at which a timer needs checking, and is only called if
+/// someone's going to use it.
+pub(crate) fn begin_idle(deadline: impl FnOnce() -> Option<TickTime>) {
+    if let Some(source) = time_source() {
+        source.set_alarm(deadline());
+        return;
+    }
+    #[cfg(feature = "tickless")]
+    tickless::begin_tickless_idle(deadline());
+    #[cfg(not(feature = "tickless"))]
+    let _ = deadline;
+}
+
+/// Called by the executor when it's done idling.
+#[inline(always)]
+pub(crate) fn end_idle() {
+    #[cfg(feature = "tickless")]
+    tickless::end_tickless_idle();
+}
+
+/// Sets up the tick counter to tick at [`TICK_HZ`] (1 kHz, by default),
+/// assuming a CPU core clock of `clock_hz`.
 ///
 /// If you use this module in your application, call this before
 /// [`run_tasks`][crate::exec::run_tasks] (or a fancier version of `run_tasks`)
 /// to set up the timer for monotonic operation.
-pub fn initialize_sys_tick(syst: &mut SYST, clock_mhz: u32) {
-    let cycles_per_millisecond = clock_mhz / 1000;
-    syst.set_reload(cycles_per_millisecond - 1);
+///
+/// # Panics
+///
+/// If `clock_hz` isn't a nonzero multiple of `TICK_HZ`. The tick is a whole
+/// number of cycles, so any other clock would make time run fast.
+#[cfg(all(feature = "systick", feature = "has-systick"))]
+pub fn initialize_sys_tick(syst: &mut SYST, clock_hz: u32) {
+    let cycles_per_tick = clock_hz / TICK_HZ as u32;
+    crate::cheap_assert!(cycles_per_tick != 0 && clock_hz % TICK_HZ as u32 == 0);
+    #[cfg(feature = "tickless")]
+    tickless::CYCLES_PER_TICK.store(cycles_per_tick, Ordering::Relaxed);
+    syst.set_reload(cycles_per_tick - 1);
     syst.clear_current();
     syst.set_clock_source(SystClkSource::Core);
     syst.enable_interrupt();
@@ -117,7 +569,21 @@ pub struct TickTime(u64);
 
 impl TickTime {
     /// Retrieves the current value of the tick counter.
+    ///
+    /// # Panics
+    ///
+    /// If the `systick` feature is off (and this isn't a hosted build), and no
+    /// [`TimeSource`] has been installed.
     pub fn now() -> Self {
+        match time_source() {
+            Some(source) => source.now(),
+            None => Self::builtin_now(),
+        }
+    }
+
+    /// Reads the SysTick-driven tick counter.
+    #[cfg(all(feature = "systick", not(feature = "hosted")))]
+    fn builtin_now() -> Self {
         // This loop will only repeat if e != e2, which means we raced the
         // systick ISR. Since that ISR only occurs once per millisecond, this
         // loop should repeat at most twice.
@@ -131,10 +597,35 @@ impl TickTime {
         }
     }
 
+    /// Reads the hosted tick counter.
+    #[cfg(feature = "hosted")]
+    fn builtin_now() -> Self {
+        TickTime(HOST_TICK.load(Ordering::Acquire))
+    }
+
+    /// Stands in for the OS's own clock when there isn't one.
+    #[cfg(not(any(feature = "systick", feature = "hosted")))]
+    fn builtin_now() -> Self {
+        panic!()
+    }
+
     /// Constructs a `TickTime` value describing a certain number of
-    /// milliseconds since the executor booted.
+    /// milliseconds since the executor booted, rounded up to a whole tick.
     pub fn from_millis_since_boot(m: u64) -> Self {
-        Self(m)
+        Self(to_ticks(m, 1_000))
+    }
+
+    /// Constructs a `TickTime` value describing a certain number of
+    /// microseconds since the executor booted, rounded up to a whole tick.
+    pub fn from_micros_since_boot(us: u64) -> Self {
+        Self(to_ticks(us, 1_000_000))
+    }
+
+    /// Constructs a `TickTime` value describing a certain number of ticks since
+    /// the executor booted. This is mostly useful when implementing a
+    /// [`TimeSource`].
+    pub const fn from_ticks_since_boot(ticks: u64) -> Self {
+        Self(ticks)
     }
 
     /// Subtracts this time from an earlier time, giving the `Duration` between
@@ -144,17 +635,33 @@ impl TickTime {
     ///
     /// If this time is not actually `>= earlier`.
     pub fn duration_since(self, earlier: TickTime) -> Duration {
-        Duration::from_millis(self.millis_since(earlier).0)
+        ticks_to_duration(self.ticks_since(earlier))
     }
 
     /// Subtracts this time from an earlier time, giving the amount of time
-    /// between them measured in `Millis`.
+    /// between them measured in `Millis`, rounded down.
     ///
     /// # Panics
     ///
     /// If this time is not actually `>= earlier`.
     pub fn millis_since(self, earlier: TickTime) -> Millis {
-        Millis(self.0.checked_sub(earlier.0).unwrap())
+        Millis(from_ticks(self.ticks_since(earlier), 1_000))
+    }
+
+    /// Subtracts this time from an earlier time, giving the amount of time
+    /// between them measured in `Micros`, rounded down.
+    ///
+    /// # Panics
+    ///
+    /// If this time is not actually `>= earlier`.
+    pub fn micros_since(self, earlier: TickTime) -> Micros {
+        Micros(from_ticks(self.ticks_since(earlier), 1_000_000))
+    }
+
+    /// Subtracts this time from an earlier time, giving the number of ticks
+    /// between them.
+    fn ticks_since(self, earlier: TickTime) -> u64 {
+        self.0.checked_sub(earlier.0).unwrap()
     }
 
     /// Checks the clock to determine how much time has elapsed since the
@@ -163,38 +670,46 @@ impl TickTime {
         Self::now().millis_since(self)
     }
 
+    /// Checks the clock to determine how much time has elapsed since the
+    /// instant recorded by `self`. Version that returns the result in
+    /// microseconds.
+    pub fn elapsed_micros(self) -> Micros {
+        Self::now().micros_since(self)
+    }
+
     /// Checks the clock to determine how much time has elapsed since the
     /// instant recorded by `self`. Convenience version that returns the result
     /// as a `Duration`.
     pub fn elapsed_duration(self) -> Duration {
-        Duration::from_millis(self.elapsed().0)
+        Self::now().duration_since(self)
     }
 
     /// Adds some milliseconds to `self`, checking for overflow. Note that since
     /// we use 64 bit ticks, overflow is unlikely in practice.
     pub fn checked_add(self, millis: Millis) -> Option<Self> {
-        self.0.checked_add(millis.0).map(TickTime)
+        self.0.checked_add(to_ticks(millis.0, 1_000)).map(TickTime)
     }
 
     /// Subtracts some milliseconds from `self`, checking for overflow. Overflow
     /// can occur if `millis` is longer than the time from boot to `self`.
     pub fn checked_sub(self, millis: Millis) -> Option<Self> {
-        self.0.checked_sub(millis.0).map(TickTime)
+        self.0.checked_sub(to_ticks(millis.0, 1_000)).map(TickTime)
     }
 }
 
 /// Add a `Duration` to a `Ticks` with normal `+` overflow behavior (i.e.
-/// checked in debug builds, optionally not checked in release builds).
+/// checked in debug builds, optionally not checked in release builds). The
+/// `Duration` is rounded up to a whole number of ticks.
 impl Add<Duration> for TickTime {
     type Output = Self;
     fn add(self, other: Duration) -> Self::Output {
-        TickTime(self.0 + other.as_millis() as u64)
+        TickTime(self.0 + duration_to_ticks(other))
     }
 }
 
 impl AddAssign<Duration> for TickTime {
     fn add_assign(&mut self, other: Duration) {
-        self.0 += other.as_millis() as u64
+        self.0 += duration_to_ticks(other)
     }
 }
 
@@ -214,10 +729,11 @@ impl From<TickTime> for u64 {
 /// multiplication. Many useful processors, such as Cortex-M0, don't have 32-bit
 /// division, much less 64-bit division.
 ///
-/// `Millis` wraps a `u64` and records a number of milliseconds. Since
-/// milliseconds are `lilos`'s unit used for internal timekeeping, this ensures
-/// that a `Millis` can be used for any deadline or timeout computation without
-/// any unit conversions or expensive arithmetic operations.
+/// `Millis` wraps a `u64` and records a number of milliseconds. At the default
+/// tick rate, milliseconds are `lilos`'s unit used for internal timekeeping,
+/// so a `Millis` can be used for any deadline or timeout computation without
+/// any unit conversions or expensive arithmetic operations. (At other tick
+/// rates, see [Changing the tick rate](self#changing-the-tick-rate).)
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Default)]
 pub struct Millis(pub u64);
 
@@ -227,7 +743,7 @@ pub struct Millis(pub u64);
 impl Add<Millis> for TickTime {
     type Output = Self;
     fn add(self, other: Millis) -> Self::Output {
-        TickTime(self.0 + other.0)
+        TickTime(self.0 + to_ticks(other.0, 1_000))
     }
 }
 
@@ -236,7 +752,7 @@ impl Add<Millis> for TickTime {
 /// builds).
 impl AddAssign<Millis> for TickTime {
     fn add_assign(&mut self, other: Millis) {
-        self.0 += other.0;
+        self.0 += to_ticks(other.0, 1_000);
     }
 }
 
@@ -252,6 +768,111 @@ impl From<u64> for Millis {
     }
 }
 
+impl From<Millis> for Duration {
+    fn from(x: Millis) -> Self {
+        Duration::from_millis(x.0)
+    }
+}
+
+/// A period of time measured in microseconds.
+///
+/// This is the finer-grained sibling of [`Millis`], for use with faster tick
+/// rates (see [Changing the tick rate](self#changing-the-tick-rate)). Like
+/// `Millis`, it's cheaper to convert to ticks than `Duration` is, particularly
+/// when the tick rate divides evenly into a million.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Default)]
+pub struct Micros(pub u64);
+
+/// Adds a number of microseconds to a `TickTime` with normal `+` overflow
+/// behavior (i.e. checked in debug builds, optionally not checked in release
+/// builds). The interval is rounded up to a whole number of ticks.
+impl Add<Micros> for TickTime {
+    type Output = Self;
+    fn add(self, other: Micros) -> Self::Output {
+        TickTime(self.0 + to_ticks(other.0, 1_000_000))
+    }
+}
+
+/// Adds a number of microseconds to a `TickTime` with normal `+=` overflow
+/// behavior (i.e. checked in debug builds, optionally not checked in release
+/// builds). The interval is rounded up to a whole number of ticks.
+impl AddAssign<Micros> for TickTime {
+    fn add_assign(&mut self, other: Micros) {
+        self.0 += to_ticks(other.0, 1_000_000);
+    }
+}
+
+impl From<Micros> for u64 {
+    fn from(x: Micros) -> Self {
+        x.0
+    }
+}
+
+impl From<u64> for Micros {
+    fn from(x: u64) -> Self {
+        Self(x)
+    }
+}
+
+impl From<Millis> for Micros {
+    fn from(x: Millis) -> Self {
+        Self(x.0 * 1_000)
+    }
+}
+
+impl From<Micros> for Duration {
+    fn from(x: Micros) -> Self {
+        Duration::from_micros(x.0)
+    }
+}
+
+/// Converts `n` of a unit that there are `unit_hz` of per second into ticks,
+/// rounding up so that sleeps are never short. Both rates are constants at
+/// every call site, so this folds down to at most a multiply and a divide --
+/// and to nothing at all when the unit is the tick.
+#[inline(always)]
+const fn to_ticks(n: u64, unit_hz: u64) -> u64 {
+    if unit_hz == TICK_HZ {
+        n
+    } else if TICK_HZ % unit_hz == 0 {
+        n * (TICK_HZ / unit_hz)
+    } else if unit_hz % TICK_HZ == 0 {
+        let per_tick = unit_hz / TICK_HZ;
+        n / per_tick + (n % per_tick != 0) as u64
+    } else {
+        // Split off whole seconds to keep the multiply from overflowing.
+        let part = (n % unit_hz) * TICK_HZ;
+        (n / unit_hz) * TICK_HZ + part / unit_hz + (part % unit_hz != 0) as u64
+    }
+}
+
+/// Converts `ticks` into a unit that there are `unit_hz` of per second,
+/// rounding down. This is the inverse of `to_ticks`.
+#[inline(always)]
+const fn from_ticks(ticks: u64, unit_hz: u64) -> u64 {
+    if unit_hz == TICK_HZ {
+        ticks
+    } else if unit_hz % TICK_HZ == 0 {
+        ticks * (unit_hz / TICK_HZ)
+    } else if TICK_HZ % unit_hz == 0 {
+        ticks / (TICK_HZ / unit_hz)
+    } else {
+        (ticks / TICK_HZ) * unit_hz + (ticks % TICK_HZ) * unit_hz / TICK_HZ
+    }
+}
+
+/// Converts a `Duration` into ticks, rounding up.
+fn duration_to_ticks(d: Duration) -> u64 {
+    d.as_secs() * TICK_HZ + to_ticks(u64::from(d.subsec_nanos()), 1_000_000_000)
+}
+
+/// Converts a number of ticks into a `Duration`, rounding down to the
+/// nanosecond.
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = from_ticks(ticks % TICK_HZ, 1_000_000_000);
+    Duration::new(ticks / TICK_HZ, nanos as u32)
+}
+
 /// Sleeps until the system time is equal to or greater than `deadline`.
 ///
 /// More precisely, `sleep_until(d)` returns a `Future` that will poll as
@@ -300,7 +921,7 @@ pub async fn sleep_until(deadline: TickTime) {
 /// If `d` is 0, this will instantly become `Ready`.
 ///
 /// `d` can be any type that can be added to a `TickTime`, which in practice
-/// means either [`Millis`] or [`Duration`].
+/// means [`Millis`], [`Micros`], or [`Duration`].
 ///
 /// This function is a thin wrapper around [`sleep_until`]. See that function's
 /// docs for examples, details, and alternatives.
@@ -424,16 +1045,21 @@ impl<A, B> Future for TimeLimited<A, B>
 ///
 /// - [`sleep_for`] can ensure a minimum delay _between_ operations, which is
 ///   different from `PeriodicGate`'s behavior.
+///
+/// The interval is kept as a whole number of ticks. If it doesn't divide
+/// evenly into ticks at your [tick rate](self#changing-the-tick-rate), it's
+/// rounded up.
 #[derive(Debug)]
 pub struct PeriodicGate {
-    interval: Millis,
+    /// Interval, in ticks.
+    interval: u64,
     next: TickTime,
 }
 
 impl From<Duration> for PeriodicGate {
     fn from(d: Duration) -> Self {
         PeriodicGate {
-            interval: Millis(d.as_millis() as u64),
+            interval: duration_to_ticks(d),
             next: TickTime::now(),
         }
     }
@@ -444,7 +1070,18 @@ impl From<Millis> for PeriodicGate {
     /// `interval`, starting right now.
     fn from(interval: Millis) -> Self {
         PeriodicGate {
-            interval,
+            interval: to_ticks(interval.0, 1_000),
+            next: TickTime::now(),
+        }
+    }
+}
+
+impl From<Micros> for PeriodicGate {
+    /// Creates a periodic gate that can be used to release execution every
+    /// `interval`, starting right now.
+    fn from(interval: Micros) -> Self {
+        PeriodicGate {
+            interval: to_ticks(interval.0, 1_000_000),
             next: TickTime::now(),
         }
     }
@@ -452,13 +1089,13 @@ impl From<Millis> for PeriodicGate {
 
 impl PeriodicGate {
     /// Creates a periodic gate that can be used to release execution every
-    /// `interval`, starting `delay` ticks in the future.
+    /// `interval`, starting `delay` from now.
     ///
     /// This can be useful for creating multiple periodic gates that operate out
     /// of phase with respect to each other.
     pub fn new_shift(interval: Millis, delay: Millis) -> Self {
         PeriodicGate {
-            interval,
+            interval: to_ticks(interval.0, 1_000),
             next: TickTime::now() + delay,
         }
     }
@@ -472,16 +1109,27 @@ impl PeriodicGate {
     /// Dropping this future does nothing in particular.
     pub async fn next_time(&mut self) {
         sleep_until(self.next).await;
-        self.next += self.interval;
+        self.next.0 += self.interval;
     }
 }
 
 /// System tick ISR. Advances the tick counter. This doesn't wake any tasks; see
-/// code in `exec` for that.
+/// code in `exec` for that. (It does pend any interrupt executors that are
+/// waiting on timers, since they can't notice the tick on their own.)
 #[doc(hidden)]
+#[cfg(all(feature = "systick", feature = "has-systick"))]
 #[exception]
 fn SysTick() {
-    if TICK.fetch_add_polyfill(1, Ordering::Release) == core::u32::MAX {
-        EPOCH.fetch_add_polyfill(1, Ordering::Release);
+    #[cfg(feature = "virtual-time")]
+    if VIRTUAL.load(Ordering::Relaxed) {
+        return;
     }
+
+    #[cfg(not(feature = "tickless"))]
+    advance_tick_counter(1);
+    #[cfg(feature = "tickless")]
+    tickless::period_ended();
+
+    #[cfg(feature = "interrupt-executor")]
+    crate::exec::pend_timer_executors();
 }
@@ -0,0 +1,215 @@
+//! Tick counter driven by the RISC-V machine timer, for the `systick` feature
+//! on RISC-V targets.
+//!
+//! The machine timer is the standard timer in a RISC-V core-local interruptor
+//! (CLINT): a free-running 64-bit `mtime` counter, and a 64-bit `mtimecmp`
+//! register for each hart that raises the machine timer interrupt whenever
+//! `mtime >= mtimecmp`. It doesn't reload on its own, so each time the
+//! interrupt fires, we move `mtimecmp` out to the next tick.
+//!
+//! Because the compare register is 64 bits wide, tickless idle is simple: we
+//! move `mtimecmp` out to the deadline, and remember how many ticks we
+//! skipped. Whoever runs next -- the ISR, or the executor when it's done
+//! idling -- credits the tick counter with the ticks that actually passed.
+//!
+//! This assumes a single hart (hart 0), and the common SiFive CLINT layout
+//! used by QEMU's `virt` machine and most microcontrollers. The registers are
+//! accessed 32 bits at a time, since that's all RV32 can do.
+
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use super::{advance_tick_counter, TICK_HZ};
+#[cfg(feature = "tickless")]
+use super::TickTime;
+
+/// Offset of hart 0's `mtimecmp` register from the CLINT base address.
+const MTIMECMP_OFFSET: usize = 0x4000;
+/// Offset of the `mtime` register from the CLINT base address.
+const MTIME_OFFSET: usize = 0xBFF8;
+
+/// Base address of the CLINT. Set by `initialize_machine_timer`.
+static CLINT_BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of `mtime` counts in a tick. Set by `initialize_machine_timer`; zero
+/// until then, which disables tickless idle.
+static CYCLES_PER_TICK: AtomicU32 = AtomicU32::new(0);
+
+/// Number of whole ticks that `mtimecmp` was moved past by tickless idle, and
+/// which haven't been credited to the tick counter yet.
+static SKIPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Sets up the tick counter to tick at [`TICK_HZ`][super::TICK_HZ] (1 kHz, by
+/// default), using the machine timer in the CLINT at `clint_base`, which counts
+/// at `timer_hz`. This is the RISC-V equivalent of `initialize_sys_tick`, and
+/// like it, should be called before [`run_tasks`][crate::exec::run_tasks].
+///
+/// This enables the machine timer interrupt, and also turns on interrupts in
+/// general (`mstatus.MIE`). RISC-V parts come out of reset with interrupts
+/// off, whereas the OS (like ARM parts) expects them to be on by the time the
+/// executor starts.
+///
+/// # Safety
+///
+/// `clint_base` must be the address of a CLINT, which the OS gets to use from
+/// here on. Nothing else should touch hart 0's `mtimecmp` register.
+///
+/// Since this turns interrupts on, any other interrupt sources that are already
+/// enabled need to be ready to be handled.
+pub unsafe fn initialize_machine_timer(clint_base: usize, timer_hz: u32) {
+    let cycles_per_tick = timer_hz / TICK_HZ as u32;
+    CLINT_BASE.store(clint_base, Ordering::Relaxed);
+    CYCLES_PER_TICK.store(cycles_per_tick, Ordering::Relaxed);
+    write_mtimecmp(read_mtime() + u64::from(cycles_per_tick));
+    // Safety: the machine timer interrupt is handled by `MachineTimer` below,
+    // which is ready to go now that the compare register is set. Anything else
+    // that interrupts us is the caller's problem, per the docs above.
+    unsafe {
+        riscv::register::mie::set_mtimer();
+        riscv::interrupt::enable();
+    }
+}
+
+/// Reads a 64-bit CLINT register at `offset`, one half at a time. If the high
+/// half changes while we're reading, we try again.
+fn read64(offset: usize) -> u64 {
+    let p = (CLINT_BASE.load(Ordering::Relaxed) + offset) as *const u32;
+    loop {
+        // Safety: our caller promised `initialize_machine_timer` a CLINT,
+        // and these reads have no side effects.
+        let (hi, lo, hi2) = unsafe {
+            (
+                p.add(1).read_volatile(),
+                p.read_volatile(),
+                p.add(1).read_volatile(),
+            )
+        };
+        if hi == hi2 {
+            break (u64::from(hi) << 32) | u64::from(lo);
+        }
+    }
+}
+
+/// Reads the free-running `mtime` counter.
+fn read_mtime() -> u64 {
+    read64(MTIME_OFFSET)
+}
+
+/// Reads hart 0's `mtimecmp` register.
+fn read_mtimecmp() -> u64 {
+    read64(MTIMECMP_OFFSET)
+}
+
+/// Writes hart 0's `mtimecmp` register, without letting it pass through a
+/// value that would cause a spurious interrupt along the way.
+fn write_mtimecmp(value: u64) {
+    let p = (CLINT_BASE.load(Ordering::Relaxed) + MTIMECMP_OFFSET) as *mut u32;
+    // Safety: `initialize_machine_timer` gave us this register.
+    unsafe {
+        p.write_volatile(u32::MAX);
+        p.add(1).write_volatile((value >> 32) as u32);
+        p.write_volatile(value as u32);
+    }
+}
+
+/// Machine timer ISR. Advances the tick counter by the number of ticks that
+/// have passed (usually one) and moves `mtimecmp` out to the next tick. Like
+/// the SysTick ISR on ARM, this doesn't wake any tasks, but does pend any
+/// interrupt executors that are waiting on timers.
+#[doc(hidden)]
+#[no_mangle]
+#[allow(non_snake_case)]
+extern "C" fn MachineTimer() {
+    crate::arch::isr_scope(|| {
+        let cycles_per_tick = u64::from(CYCLES_PER_TICK.load(Ordering::Relaxed));
+        let cmp = read_mtimecmp();
+        let now = read_mtime();
+        if now < cmp || cycles_per_tick == 0 {
+            // Spurious, or the compare register was moved after the interrupt
+            // was raised.
+            return;
+        }
+        // Ticks whose boundaries we've passed since cmp, plus the one at cmp.
+        let ticks = (now - cmp) / cycles_per_tick + 1;
+        write_mtimecmp(cmp + ticks * cycles_per_tick);
+        let ticks = ticks as u32 + SKIPPED.swap(0, Ordering::Relaxed);
+
+        // Unlike SysTick, the timer keeps running in virtual time, but it
+        // doesn't get to move the clock.
+        #[cfg(feature = "virtual-time")]
+        if super::VIRTUAL.load(Ordering::Relaxed) {
+            return;
+        }
+        advance_tick_counter(ticks);
+
+        #[cfg(feature = "interrupt-executor")]
+        crate::exec::pend_timer_executors();
+    });
+}
+
+/// Called by the executor just before it idles. If `deadline` (the earliest
+/// time any task is waiting for, if any) is more than a tick away, moves
+/// `mtimecmp` out to it.
+#[cfg(feature = "tickless")]
+pub(super) fn begin_tickless_idle(deadline: Option<TickTime>) {
+    #[cfg(feature = "virtual-time")]
+    if super::VIRTUAL.load(Ordering::Relaxed) {
+        return;
+    }
+    let cycles_per_tick = CYCLES_PER_TICK.load(Ordering::Relaxed);
+    if cycles_per_tick == 0 {
+        return;
+    }
+
+    crate::arch::with_interrupts_masked(|| {
+        let cmp = read_mtimecmp();
+        if read_mtime() >= cmp {
+            // A tick is due. Let it happen.
+            return;
+        }
+        let ticks = match deadline {
+            Some(d) => u64::from(d).saturating_sub(u64::from(TickTime::now())),
+            None => u64::MAX,
+        };
+        // Keep well clear of overflowing the tick count handed to the ISR.
+        let extra = ticks.saturating_sub(1).min(u64::from(u32::MAX / 2)) as u32;
+        if extra == 0 {
+            return;
+        }
+        write_mtimecmp(cmp + u64::from(extra) * u64::from(cycles_per_tick));
+        SKIPPED.store(extra, Ordering::Relaxed);
+    });
+}
+
+/// Called by the executor when it's done idling. If we were woken before the
+/// deadline, credits the tick counter with the ticks that have passed, and
+/// moves `mtimecmp` back to the next tick.
+#[cfg(feature = "tickless")]
+pub(super) fn end_tickless_idle() {
+    crate::arch::with_interrupts_masked(|| {
+        let skipped = SKIPPED.load(Ordering::Relaxed);
+        if skipped == 0 {
+            // Either we didn't move the compare register, or the ISR has
+            // already caught up.
+            return;
+        }
+        let cmp = read_mtimecmp();
+        let now = read_mtime();
+        if now >= cmp {
+            // The deadline has passed, but we've got interrupts masked.
+            // Leave it to the ISR.
+            return;
+        }
+        let cycles_per_tick = u64::from(CYCLES_PER_TICK.load(Ordering::Relaxed));
+        // Where the compare register would have been without tickless idle.
+        let next = cmp - u64::from(skipped) * cycles_per_tick;
+        let passed = if now >= next {
+            (now - next) / cycles_per_tick + 1
+        } else {
+            0
+        };
+        // `now < cmp` means this is less than `skipped`.
+        advance_tick_counter(passed as u32);
+        write_mtimecmp(next + passed * cycles_per_tick);
+        SKIPPED.store(0, Ordering::Relaxed);
+    });
+}
@@ -0,0 +1,212 @@
+//! Tickless idle, enabled by the `tickless` feature.
+//!
+//! Normally SysTick interrupts the processor once per tick, whether or not
+//! anything is waiting for that tick. In tickless mode, when the executor is
+//! about to idle, it tells us the earliest deadline in its timer list, and we
+//! stretch the current SysTick period to end at that deadline (or as close as
+//! the 24-bit SysTick counter can reach). If something else wakes the
+//! processor first, we work out how much of the stretched period has passed,
+//! credit the tick counter with the whole ticks, and shorten the next period so
+//! that ticks stay aligned with where they would have been.
+//!
+//! While the period is stretched, the tick counter isn't updated, so an ISR
+//! that reads `TickTime::now()` during idle sees the time at which idle began.
+//! Task code never sees this, since the counter is corrected before any task
+//! is polled.
+//!
+//! Reprogramming SysTick costs a few cycles each time, so the clock can drift
+//! very slightly behind the core clock when the processor idles a lot.
+//!
+//! On RISC-V, the machine timer's compare register is simply moved out to the
+//! deadline; see the `mtimer` module.
+//!
+//! In hosted builds there's no SysTick; instead, the executor thread parks
+//! until the next deadline, rather than waking every millisecond.
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "hosted")] {
+        use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+        use core::time::Duration;
+
+        use super::TickTime;
+    } else if #[cfg(feature = "has-systick")] {
+        use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+        use cortex_m::peripheral::{SCB, SYST};
+
+        use super::{advance_tick_counter as advance, TickTime};
+    } else if #[cfg(feature = "has-mtimer")] {
+        // The machine timer has a 64-bit compare register, so it can simply
+        // be told when to go off. See the `mtimer` module.
+        pub(super) use super::mtimer::{begin_tickless_idle, end_tickless_idle};
+    }
+}
+
+/// Longest period SysTick can count, in cycles.
+#[cfg(feature = "has-systick")]
+const MAX_PERIOD: u32 = 1 << 24;
+
+/// Number of SysTick cycles in a tick. Set by `initialize_sys_tick`; zero
+/// until then, which disables tickless idle.
+#[cfg(feature = "has-systick")]
+pub(super) static CYCLES_PER_TICK: AtomicU32 = AtomicU32::new(0);
+
+/// Number of ticks that will have passed when the current SysTick period
+/// ends.
+#[cfg(feature = "has-systick")]
+static PERIOD_TICKS: AtomicU32 = AtomicU32::new(1);
+
+/// Number of cycles of the first tick in a stretched period that had already
+/// passed when the period began.
+#[cfg(feature = "has-systick")]
+static PERIOD_OFFSET: AtomicU32 = AtomicU32::new(0);
+
+/// Set when the current SysTick period isn't exactly one tick long, so that
+/// the regular period needs to be restored when it ends.
+#[cfg(feature = "has-systick")]
+static ADJUSTED: AtomicBool = AtomicBool::new(false);
+
+/// Reprograms SysTick to end its current period after `cycles` cycles, and
+/// then keep using that period until told otherwise.
+#[cfg(feature = "has-systick")]
+fn set_period(cycles: u32) {
+    // Safety: once `initialize_sys_tick` has been called, SysTick belongs to
+    // the OS, so we're not stepping on anyone by writing its registers.
+    unsafe {
+        let syst = &*SYST::PTR;
+        // A reload value of zero would stop the counter, so never go below
+        // two cycles.
+        syst.rvr.write(cycles.max(2) - 1);
+        // Writing any value clears the counter, so that it reloads from the
+        // new period on the next cycle.
+        syst.cvr.write(0);
+    }
+}
+
+/// Called from the SysTick ISR at the end of each period. Advances the tick
+/// counter by however many ticks the period covered, and puts back the
+/// regular period if it was adjusted.
+#[cfg(feature = "has-systick")]
+pub(super) fn period_ended() {
+    let ticks = if ADJUSTED.load(Ordering::Relaxed) {
+        ADJUSTED.store(false, Ordering::Relaxed);
+        set_period(CYCLES_PER_TICK.load(Ordering::Relaxed));
+        let ticks = PERIOD_TICKS.load(Ordering::Relaxed);
+        PERIOD_TICKS.store(1, Ordering::Relaxed);
+        ticks
+    } else {
+        1
+    };
+    advance(ticks);
+}
+
+/// Called by the executor just before it idles. If `deadline` (the earliest
+/// time any task is waiting for, if any) is more than a tick away, stretches
+/// the current SysTick period to end there.
+#[cfg(feature = "has-systick")]
+pub(super) fn begin_tickless_idle(deadline: Option<TickTime>) {
+    #[cfg(feature = "virtual-time")]
+    if super::VIRTUAL.load(Ordering::Relaxed) {
+        return;
+    }
+    let cycles_per_tick = CYCLES_PER_TICK.load(Ordering::Relaxed);
+    if cycles_per_tick == 0 {
+        return;
+    }
+
+    crate::arch::with_interrupts_masked(|| {
+        if SCB::is_pendst_pending() {
+            // A tick is due. Let it happen.
+            return;
+        }
+        let ticks = match deadline {
+            Some(d) => u64::from(d).saturating_sub(u64::from(TickTime::now())),
+            None => u64::MAX,
+        };
+        // Cycles left until the next tick.
+        let remaining = SYST::get_current();
+        let max_extra = (MAX_PERIOD - remaining) / cycles_per_tick;
+        let extra = ticks.saturating_sub(1).min(u64::from(max_extra)) as u32;
+        if extra == 0 {
+            return;
+        }
+        set_period(remaining + extra * cycles_per_tick);
+        PERIOD_TICKS.store(extra + 1, Ordering::Relaxed);
+        PERIOD_OFFSET.store(cycles_per_tick - remaining, Ordering::Relaxed);
+        ADJUSTED.store(true, Ordering::Relaxed);
+    });
+}
+
+/// Called by the executor when it's done idling. If we were woken before a
+/// stretched period ended, credits the tick counter with the ticks that have
+/// passed, and shortens the period to end at the next tick.
+#[cfg(feature = "has-systick")]
+pub(super) fn end_tickless_idle() {
+    crate::arch::with_interrupts_masked(|| {
+        if PERIOD_TICKS.load(Ordering::Relaxed) == 1 {
+            // Either we didn't stretch the period, or it ended and the ISR
+            // has cleaned up.
+            return;
+        }
+        let current = SYST::get_current();
+        if SCB::is_pendst_pending() {
+            // The period has ended (possibly while we were reading the
+            // counter), but we've got interrupts masked. Leave it to the ISR.
+            return;
+        }
+        let cycles_per_tick = CYCLES_PER_TICK.load(Ordering::Relaxed);
+        let elapsed = SYST::get_reload() - current
+            + PERIOD_OFFSET.load(Ordering::Relaxed);
+        advance(elapsed / cycles_per_tick);
+        // Finish out the current tick, after which the ISR will restore the
+        // regular period.
+        set_period(cycles_per_tick - elapsed % cycles_per_tick);
+        PERIOD_TICKS.store(1, Ordering::Relaxed);
+    });
+}
+
+/// Deadline the hosted executor is idling until, in ticks, or `u64::MAX` for
+/// none. Only meaningful while `IDLING` is set.
+#[cfg(feature = "hosted")]
+static IDLE_UNTIL: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Set while the hosted executor is idling in tickless mode.
+#[cfg(feature = "hosted")]
+static IDLING: AtomicBool = AtomicBool::new(false);
+
+/// Called by the executor just before it idles, with the earliest time any
+/// task is waiting for, if any.
+#[cfg(feature = "hosted")]
+pub(super) fn begin_tickless_idle(deadline: Option<TickTime>) {
+    IDLE_UNTIL.store(deadline.map_or(u64::MAX, u64::from), Ordering::Relaxed);
+    IDLING.store(true, Ordering::Release);
+}
+
+/// Called by the executor when it's done idling.
+#[cfg(feature = "hosted")]
+pub(super) fn end_tickless_idle() {
+    IDLING.store(false, Ordering::Release);
+}
+
+/// Returns how long the hosted executor thread should park for when idle: a
+/// single tick normally, until the next deadline in tickless idle, or until
+/// woken (`None`) if there's no deadline. Virtual time doesn't pass on its
+/// own, so while it's in effect we stick to single ticks.
+#[cfg(feature = "hosted")]
+pub(crate) fn host_park_timeout() -> Option<Duration> {
+    #[cfg(feature = "virtual-time")]
+    let idling = IDLING.load(Ordering::Acquire)
+        && !super::VIRTUAL.load(Ordering::Acquire);
+    #[cfg(not(feature = "virtual-time"))]
+    let idling = IDLING.load(Ordering::Acquire);
+    if !idling {
+        return Some(Duration::from_millis(1));
+    }
+    match IDLE_UNTIL.load(Ordering::Relaxed) {
+        u64::MAX => None,
+        until => {
+            let now = u64::from(TickTime::now());
+            Some(super::ticks_to_duration(until.saturating_sub(now).max(1)))
+        }
+    }
+}
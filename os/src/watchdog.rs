@@ -0,0 +1,181 @@
+//! Software watchdog that checks each task is still making progress.
+//!
+//! **Note:** this module is only available if the `time` feature is present.
+//!
+//! A hardware watchdog (like the STM32 IWDG) resets the processor unless it's
+//! fed regularly. Feeding it from a task shows that the executor is still
+//! running, but not that any _other_ task is: a task can be stuck waiting for
+//! an event that never comes while everything else carries on. A [`Watchdog`]
+//! closes that gap. Each task that wants to be watched registers with a
+//! check-in period, and checks in at least that often as it does its work. A
+//! supervisor task checks on them all periodically, reports any that have
+//! missed their deadline, and feeds the hardware watchdog only if none have:
+//!
+//! ```ignore
+//! static WATCHDOG: Watchdog<4> = Watchdog::new();
+//!
+//! async fn sensor_task() -> Infallible {
+//!     WATCHDOG.register(tasks::sensor.index(), Millis(100));
+//!     loop {
+//!         read_sensor().await;
+//!         WATCHDOG.check_in(tasks::sensor.index());
+//!     }
+//! }
+//!
+//! async fn supervisor(iwdg: IWDG) -> Infallible {
+//!     WATCHDOG.supervise(
+//!         Millis(50),
+//!         |task| log_missed(task),
+//!         || iwdg.kr.write(|w| w.key().reset()),
+//!     ).await
+//! }
+//! ```
+//!
+//! If a task stays stuck, the hardware watchdog goes unfed and resets the
+//! system. The supervisor period plus the longest check-in period should be
+//! comfortably shorter than the hardware watchdog's timeout, or it'll reset
+//! the system before the supervisor has a chance to report anything.
+//!
+//! Tasks are identified by their index in the executor's task array, but the
+//! watchdog doesn't otherwise interact with the executor; it's fine to use
+//! other numbers if that's more convenient.
+
+use core::cell::Cell;
+use core::convert::Infallible;
+
+use crate::arch::critical_section;
+use crate::time::{Millis, PeriodicGate, TickTime};
+
+/// Check-in state for up to `N` tasks, numbered from 0. See the [module
+/// docs](self) for more.
+///
+/// This is intended to be stored in a `static`, so that tasks can check in
+/// without being handed a reference.
+#[derive(Debug)]
+pub struct Watchdog<const N: usize> {
+    entries: [Entry; N],
+}
+
+/// Check-in state for a single task.
+#[derive(Debug)]
+struct Entry {
+    /// How often the task has promised to check in, or `None` if it isn't
+    /// registered.
+    period: Cell<Option<Millis>>,
+    /// Time by which the task must next check in.
+    deadline: Cell<TickTime>,
+}
+
+// Safety: the `Cell`s in the table are only accessed inside critical sections.
+unsafe impl<const N: usize> Sync for Watchdog<N> {}
+
+impl<const N: usize> Watchdog<N> {
+    /// Creates a watchdog with no tasks registered.
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ENTRY: Entry = Entry {
+            period: Cell::new(None),
+            deadline: Cell::new(TickTime::from_ticks_since_boot(0)),
+        };
+        Self { entries: [ENTRY; N] }
+    }
+
+    /// Starts watching `task`, which promises to call [`check_in`] at least
+    /// every `period` from now on. Registering a task again changes its
+    /// period.
+    ///
+    /// [`check_in`]: Watchdog::check_in
+    ///
+    /// # Panics
+    ///
+    /// If `task` is `N` or greater.
+    pub fn register(&self, task: usize, period: Millis) {
+        let entry = &self.entries[task];
+        critical_section(|| {
+            entry.period.set(Some(period));
+            entry.deadline.set(TickTime::now() + period);
+        });
+    }
+
+    /// Stops watching `task`, e.g. because it's about to stop doing periodic
+    /// work for a while.
+    ///
+    /// # Panics
+    ///
+    /// If `task` is `N` or greater.
+    pub fn unregister(&self, task: usize) {
+        let entry = &self.entries[task];
+        critical_section(|| entry.period.set(None));
+    }
+
+    /// Records that `task` is making progress, giving it until one period from
+    /// now to check in again. If `task` isn't registered, this does nothing.
+    ///
+    /// This can be called from anywhere, including ISRs.
+    ///
+    /// # Panics
+    ///
+    /// If `task` is `N` or greater.
+    pub fn check_in(&self, task: usize) {
+        let entry = &self.entries[task];
+        critical_section(|| {
+            if let Some(period) = entry.period.get() {
+                entry.deadline.set(TickTime::now() + period);
+            }
+        });
+    }
+
+    /// Checks every registered task against its deadline, calling `on_missed`
+    /// with the index of each one that's overdue. Returns `true` if they were
+    /// all on time.
+    ///
+    /// A task that's overdue stays overdue, and is reported again by later
+    /// calls, until it checks in.
+    pub fn check(&self, mut on_missed: impl FnMut(usize)) -> bool {
+        let now = TickTime::now();
+        let mut healthy = true;
+        for (i, entry) in self.entries.iter().enumerate() {
+            let overdue = critical_section(|| {
+                entry.period.get().is_some() && entry.deadline.get() < now
+            });
+            if overdue {
+                healthy = false;
+                on_missed(i);
+            }
+        }
+        healthy
+    }
+
+    /// Runs a supervisor loop forever: every `interval`, checks the tasks as
+    /// in [`check`][Watchdog::check], reporting overdue ones to `on_missed`,
+    /// and calls `feed` if none were overdue.
+    ///
+    /// `feed` is where you feed the hardware watchdog.
+    ///
+    /// # Cancellation
+    ///
+    /// **Cancel safety:** Strict.
+    ///
+    /// Dropping this future stops supervising, which will usually let the
+    /// hardware watchdog reset the system.
+    pub async fn supervise(
+        &self,
+        interval: Millis,
+        mut on_missed: impl FnMut(usize),
+        mut feed: impl FnMut(),
+    ) -> Infallible {
+        let mut gate = PeriodicGate::from(interval);
+        loop {
+            gate.next_time().await;
+            if self.check(&mut on_missed) {
+                feed();
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for Watchdog<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,8 @@
+//! OS test suite, hosted wrapper with a 10 kHz tick.
+//!
+//! This is the same as the `hosted` wrapper, except that the OS is built with
+//! `tick-hz-10000`, so that a tick is shorter than a millisecond.
+
+fn main() {
+    lilos_testsuite::run_test_suite()
+}
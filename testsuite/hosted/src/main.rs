@@ -0,0 +1,8 @@
+//! OS test suite, hosted wrapper.
+//!
+//! This runs the test suite on the build machine using the OS's `hosted`
+//! backend. No hardware required.
+
+fn main() {
+    lilos_testsuite::run_test_suite()
+}
@@ -0,0 +1,24 @@
+//! OS test suite, RISC-V wrapper for QEMU's `virt` machine.
+
+#![no_std]
+#![no_main]
+
+use riscv_semihosting::{debug, hprintln};
+
+/// Address of the CLINT on QEMU's `virt` machine.
+const CLINT_BASE: usize = 0x0200_0000;
+
+/// The `virt` machine's timer counts at 10 MHz, whatever the CPU is doing.
+const TIMER_HZ: u32 = 10_000_000;
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    lilos_testsuite::run_test_suite(CLINT_BASE, TIMER_HZ)
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
+    hprintln!("{}", info);
+    debug::exit(debug::EXIT_FAILURE);
+    loop {}
+}
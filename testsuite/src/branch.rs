@@ -0,0 +1,118 @@
+use core::cell::Cell;
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+
+use lilos::branch::{self, Either};
+use lilos::exec::{self, Notify};
+use lilos::time::{self, Millis, TickTime};
+
+/// Wraps `fut` so that each poll bumps `count`.
+fn counted<'a, F: Future>(
+    count: &'a Cell<usize>,
+    mut fut: Pin<&'a mut F>,
+) -> impl Future<Output = F::Output> + 'a {
+    futures::future::poll_fn(move |cx| {
+        count.set(count.get() + 1);
+        fut.as_mut().poll(cx)
+    })
+}
+
+/// Checks that `join` produces the results of both futures.
+pub async fn test_join() {
+    let (a, b) = branch::join(
+        async { exec::yield_cpu().await; 1 },
+        async { 2 },
+    ).await;
+    assert_eq!((a, b), (1, 2));
+}
+
+/// Checks that a branch blocked on a timer isn't polled when its sibling (or
+/// its nested siblings) wake up.
+pub async fn test_select_polls_woken_branch() {
+    let polls = Cell::new(0);
+    let sleep = pin!(time::sleep_for(Millis(100)));
+    let sleeper = counted(&polls, sleep);
+    let worker = branch::join(
+        async {
+            for _ in 0..3 {
+                exec::yield_cpu().await;
+            }
+        },
+        async {
+            exec::yield_cpu().await;
+        },
+    );
+    match branch::select(sleeper, worker).await {
+        Either::Left(()) => panic!("sleeper should not finish"),
+        Either::Right(_) => (),
+    }
+    // Just the initial poll.
+    assert_eq!(polls.get(), 1);
+}
+
+/// Checks how a branch waiting on a `Notify` gets woken.
+pub async fn test_notify_wakes_branch() {
+    static NOTIFY: Notify = Notify::new();
+
+    let polls = Cell::new(0);
+    let waiter = futures::future::poll_fn(|cx| {
+        polls.set(polls.get() + 1);
+        NOTIFY.subscribe(cx.waker());
+        Poll::<()>::Pending
+    });
+    let worker = async {
+        exec::yield_cpu().await;
+        exec::yield_cpu().await;
+        NOTIFY.notify();
+        exec::yield_cpu().await;
+        exec::yield_cpu().await;
+    };
+    branch::select(pin!(waiter), worker).await;
+
+    if cfg!(feature = "foreign-wakers") {
+        // The Notify wakes exactly the waiting branch: polled at start, and
+        // after the notify.
+        assert_eq!(polls.get(), 2);
+    } else {
+        // The Notify can only track the task, so the waiting branch gets
+        // polled every time the task is.
+        assert_eq!(polls.get(), 5);
+    }
+}
+
+/// Checks that two branches waiting on one `Notify` let the executor idle,
+/// rather than taking turns displacing each other from the `Notify`.
+pub async fn test_notify_two_waiting_branches() {
+    static NOTIFY: Notify = Notify::new();
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    let polls = Cell::new(0);
+    let waiter = || futures::future::poll_fn(|cx| {
+        polls.set(polls.get() + 1);
+        if DONE.load(Ordering::SeqCst) {
+            Poll::Ready(())
+        } else {
+            NOTIFY.subscribe(cx.waker());
+            Poll::Pending
+        }
+    });
+    let deadline = TickTime::now() + crate::A_BIT;
+    crate::IDLE_DEADLINE.store(0, Ordering::SeqCst);
+    branch::join3(waiter(), waiter(), async {
+        time::sleep_until(deadline).await;
+        DONE.store(true, Ordering::SeqCst);
+        NOTIFY.notify();
+    }).await;
+
+    // Each waiter is polled at most three times: at the start, when the timer
+    // wakes the task, and after the notify. Waiters that kept displacing each
+    // other would keep getting polled until the timer went off.
+    assert!(polls.get() <= 6, "waiters polled {} times", polls.get());
+    // The executor idled waiting for the timer.
+    assert_eq!(
+        crate::IDLE_DEADLINE.load(Ordering::SeqCst),
+        u64::from(deadline) as u32,
+    );
+}
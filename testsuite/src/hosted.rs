@@ -0,0 +1,112 @@
+//! Tests specific to the hosted backend.
+
+use core::convert::Infallible;
+use core::future::Future;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use lilos::exec::{self, Notify};
+use lilos::time::{self, Millis, TickTime, TimeSource};
+
+/// Suites that need an executor, or a clock, to themselves, each of which is
+/// run in a child process.
+const CHILD_SUITES: &[&str] = &["priority", "priority-aliased", "time-source"];
+
+/// Runs each of the `CHILD_SUITES` in a child process, and checks that it
+/// passed.
+pub fn run_child_suites() {
+    let exe = std::env::current_exe().unwrap();
+    for suite in CHILD_SUITES {
+        let status = Command::new(&exe).arg(suite).status().unwrap();
+        assert!(status.success(), "{suite} suite failed: {status}");
+    }
+}
+
+/// Runs one of the `CHILD_SUITES`, in this process.
+pub fn run_child_suite(suite: &str) -> ! {
+    match suite {
+        "priority" => crate::priority::run(false),
+        "priority-aliased" => crate::priority::run(true),
+        "time-source" => run_alone("hosted::test_custom_time_source", test_custom_time_source()),
+        _ => panic!("no suite named {suite}"),
+    }
+}
+
+/// Runs `test` as the only task, and exits when it passes.
+fn run_alone(name: &str, test: impl Future<Output = ()>) -> ! {
+    let task = pin!(task_alone(name, test));
+    exec::run_tasks(&mut [task], exec::ALL_TASKS)
+}
+
+async fn task_alone(name: &str, test: impl Future<Output = ()>) -> Infallible {
+    crate::out!("{name}... ");
+    test.await;
+    crate::outln!("OK");
+    crate::exit_success();
+    unreachable!()
+}
+
+/// Checks that another thread, standing in for an ISR, can wake a task that's
+/// waiting on a `Notify`.
+pub async fn test_notify_from_thread() {
+    static EVENT: Notify = Notify::new();
+    static FLAG: AtomicBool = AtomicBool::new(false);
+
+    let t = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(5));
+        FLAG.store(true, Ordering::SeqCst);
+        EVENT.notify();
+    });
+
+    EVENT.until(|| FLAG.load(Ordering::SeqCst)).await;
+    t.join().unwrap();
+}
+
+/// A `TimeSource` driven by a thread, standing in for a hardware timer.
+struct ThreadClock {
+    ticks: AtomicU64,
+    /// Last alarm requested, or `u64::MAX` for none.
+    alarm: AtomicU64,
+}
+
+impl TimeSource for ThreadClock {
+    fn now(&self) -> TickTime {
+        TickTime::from_ticks_since_boot(self.ticks.load(Ordering::SeqCst))
+    }
+
+    fn set_alarm(&self, deadline: Option<TickTime>) {
+        let deadline = deadline.map_or(u64::MAX, u64::from);
+        self.alarm.store(deadline, Ordering::SeqCst);
+    }
+}
+
+/// Checks that timers work on top of a custom `TimeSource`, and that the
+/// executor asks it for an alarm before idling.
+///
+/// This replaces the clock for good, and leaves a thread ticking the new one,
+/// so it runs in a child process of its own.
+async fn test_custom_time_source() {
+    static CLOCK: ThreadClock = ThreadClock {
+        ticks: AtomicU64::new(0),
+        alarm: AtomicU64::new(u64::MAX),
+    };
+
+    // Pick up where the OS's own clock left off, so that time doesn't go
+    // backwards for anyone.
+    CLOCK.ticks.store(u64::from(TickTime::now()), Ordering::SeqCst);
+    time::set_time_source(&CLOCK);
+
+    thread::spawn(|| loop {
+        thread::sleep(Duration::from_millis(1));
+        CLOCK.ticks.fetch_add(1, Ordering::SeqCst);
+        time::alarm_fired();
+    });
+
+    let target = TickTime::now() + Millis(10);
+    time::sleep_until(target).await;
+    assert!(TickTime::now() >= target);
+    assert_eq!(CLOCK.alarm.load(Ordering::SeqCst), u64::from(target));
+}
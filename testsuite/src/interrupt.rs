@@ -0,0 +1,107 @@
+//! Tests for the interrupt executor.
+//!
+//! On hardware, the interrupt executor runs from PendSV. In hosted builds,
+//! there are no interrupts, so "pending" it just runs it on the spot, as if
+//! its interrupt had preempted whatever woke it.
+
+use core::convert::Infallible;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[cfg(not(feature = "hosted"))]
+use cortex_m_rt::exception;
+
+use lilos::exec::{InterruptExecutor, Notify};
+use lilos::time::{self, Millis, TickTime};
+
+/// Index of the urgent task, which comes right after all the tasks on the
+/// main executor.
+pub const URGENT_TASK: usize = crate::MAIN_TASKS;
+
+pub static EXECUTOR: InterruptExecutor = InterruptExecutor::new(URGENT_TASK, pend);
+
+#[cfg(not(feature = "hosted"))]
+fn pend() {
+    cortex_m::peripheral::SCB::set_pendsv();
+}
+
+#[cfg(feature = "hosted")]
+fn pend() {
+    EXECUTOR.run();
+}
+
+#[cfg(not(feature = "hosted"))]
+#[exception]
+fn PendSV() {
+    EXECUTOR.run();
+}
+
+/// Requests for the urgent task. Zero means no request.
+static REQUEST: AtomicUsize = AtomicUsize::new(0);
+const PING: usize = 1;
+const SLEEP: usize = 2;
+/// Signaled when a request is made.
+static REQUESTED: Notify = Notify::new();
+
+/// Number of requests completed.
+static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+/// Signaled when a request is completed.
+static DONE: Notify = Notify::new();
+/// Set if the urgent task ever finds itself outside the PendSV handler.
+static WRONG_CONTEXT: AtomicBool = AtomicBool::new(false);
+
+/// Serves requests from the tests on the interrupt executor.
+pub async fn task_urgent() -> Infallible {
+    loop {
+        let request = REQUESTED.until(|| {
+            match REQUEST.load(Ordering::SeqCst) {
+                0 => None,
+                r => {
+                    REQUEST.store(0, Ordering::SeqCst);
+                    Some(r)
+                }
+            }
+        }).await;
+
+        #[cfg(not(feature = "hosted"))]
+        {
+            use cortex_m::peripheral::scb::{Exception, VectActive};
+            use cortex_m::peripheral::SCB;
+
+            if SCB::vect_active() != VectActive::Exception(Exception::PendSV) {
+                WRONG_CONTEXT.store(true, Ordering::SeqCst);
+            }
+        }
+
+        if request == SLEEP {
+            time::sleep_for(Millis(3)).await;
+        }
+
+        let n = COMPLETED.load(Ordering::SeqCst);
+        COMPLETED.store(n + 1, Ordering::SeqCst);
+        DONE.notify();
+    }
+}
+
+/// Hands `request` to the urgent task and waits for it to finish.
+async fn request(request: usize) {
+    let before = COMPLETED.load(Ordering::SeqCst);
+    REQUEST.store(request, Ordering::SeqCst);
+    REQUESTED.notify();
+    DONE.until(|| COMPLETED.load(Ordering::SeqCst) != before).await;
+}
+
+/// Checks that a `Notify` can wake a task on the interrupt executor, and vice
+/// versa.
+pub async fn test_notify_across_executors() {
+    request(PING).await;
+    request(PING).await;
+    assert!(!WRONG_CONTEXT.load(Ordering::SeqCst));
+}
+
+/// Checks that tasks on the interrupt executor can sleep.
+pub async fn test_sleep_on_interrupt_executor() {
+    let start = TickTime::now();
+    request(SLEEP).await;
+    assert!(start.elapsed() >= Millis(3));
+    assert!(!WRONG_CONTEXT.load(Ordering::SeqCst));
+}
@@ -3,67 +3,285 @@
 //! The test suite is SoC-independent, but we build and test on STM32F407
 //! because Cargo's feature resolution plus `cortex-m-rt`'s feature handling
 //! means that every binary in this workspace has to target the same SoC. Sigh.
+//!
+//! On RISC-V, the tests run under QEMU's `virt` machine; see the `qemu-riscv`
+//! wrapper.
+//!
+//! With the `hosted` feature, the same tests run on the build machine against
+//! the OS's hosted backend, reporting to stdout instead of semihosting.
 
-#![no_std]
+#![cfg_attr(not(feature = "hosted"), no_std)]
 
 mod list;
 mod spsc;
 mod mutex;
 mod handoff;
+#[cfg(feature = "big-flash")]
+mod branch;
+#[cfg(feature = "big-flash")]
+mod pool;
+#[cfg(feature = "big-flash")]
+mod watchdog;
+#[cfg(feature = "interrupt-executor")]
+mod interrupt;
+#[cfg(feature = "hosted")]
+mod hosted;
+#[cfg(feature = "hosted")]
+mod priority;
+#[cfg(any(feature = "wake-bits-64", target_pointer_width = "64"))]
+mod wake_bits;
 
 use core::convert::Infallible;
-use core::pin::pin;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "big-flash")]
+use core::sync::atomic::AtomicU32;
 use futures::FutureExt;
 
-use cortex_m_semihosting::hprintln;
 use lilos::exec;
 use lilos::time;
 
+#[cfg(feature = "hosted")]
+use std::{print as out, println as outln};
+#[cfg(all(not(feature = "hosted"), target_arch = "arm"))]
+use cortex_m_semihosting::{hprint as out, hprintln as outln};
+#[cfg(all(not(feature = "hosted"), target_arch = "riscv32"))]
+use riscv_semihosting::{hprint as out, hprintln as outln};
+
+#[cfg(feature = "hosted")]
+fn exit_success() {
+    std::process::exit(0);
+}
+
+#[cfg(all(not(feature = "hosted"), target_arch = "arm"))]
+fn exit_success() {
+    cortex_m_semihosting::debug::exit(Ok(()));
+}
+
+#[cfg(all(not(feature = "hosted"), target_arch = "riscv32"))]
+fn exit_success() {
+    riscv_semihosting::debug::exit(Ok(()));
+}
+
+#[cfg(all(not(feature = "hosted"), target_arch = "arm"))]
 pub fn run_test_suite(hz: u32) -> ! {
     // Check out peripherals from the runtime.
     let mut cp = cortex_m::Peripherals::take().unwrap();
 
-    // Tasks
-    let coordinator = pin!(task_coordinator());
-    let flag_auto = pin!(task_set_a_flag_then_halt(&AUTO_FLAG));
-    let flag_manual = pin!(task_set_a_flag_then_halt(&MUST_START_FLAG));
-    let flag_manual2 = pin!(task_set_a_flag_then_halt(&MUST_NOT_START_FLAG));
-    let waiting_for_notify = pin!(async {
-        NOTIFY.until_next().await;
-        NOTIFY_REACHED.store(true, Ordering::SeqCst);
-        block_forever().await
-    });
+    time::initialize_sys_tick(&mut cp.SYST, hz);
+    // Poll budgets and time slices are measured with the cycle counter, where
+    // there is one, and it doesn't run out of reset.
+    #[cfg(any(feature = "poll-budget", feature = "time-slice"))]
+    {
+        cp.DCB.enable_trace();
+        cp.DWT.enable_cycle_counter();
+    }
+    run_tasks()
+}
+
+/// Runs the test suite on a RISC-V part, using the machine timer in the CLINT
+/// at `clint_base`, which counts at `timer_hz`.
+#[cfg(all(not(feature = "hosted"), target_arch = "riscv32"))]
+pub fn run_test_suite(clint_base: usize, timer_hz: u32) -> ! {
+    // Safety: the wrapper promises that this is the CLINT, and nothing else
+    // in the test suite uses it.
+    unsafe {
+        time::initialize_machine_timer(clint_base, timer_hz);
+    }
+    run_tasks()
+}
+
+/// Runs the test suite on the build machine. The suites that need a process to
+/// themselves are run first, by starting this program again with the name of
+/// the suite as its argument.
+#[cfg(feature = "hosted")]
+pub fn run_test_suite() -> ! {
+    match std::env::args().nth(1) {
+        Some(suite) => hosted::run_child_suite(&suite),
+        None => {
+            hosted::run_child_suites();
+            run_tasks()
+        }
+    }
+}
 
-    let start_mask = 0b011;
+fn run_tasks() -> ! {
+    lilos::run_task_table! {
+        tasks {
+            coordinator: task_coordinator(),
+            flag_auto: task_set_a_flag_then_halt(&AUTO_FLAG),
+            flag_manual: task_set_a_flag_then_halt(&MUST_START_FLAG),
+            flag_manual2: task_set_a_flag_then_halt(&MUST_NOT_START_FLAG),
+            waiting_for_notify: async {
+                NOTIFY.until_next().await;
+                NOTIFY_REACHED.store(true, Ordering::SeqCst);
+                block_forever().await
+            },
+            #[cfg(feature = "task-control")]
+            controlled: task_controlled(),
+        }
+        start: [coordinator, flag_auto]
+        run: |tasks, start| start_executors(tasks, start)
+    }
+}
 
-    time::initialize_sys_tick(&mut cp.SYST, hz);
-    exec::run_tasks(
-        &mut [
-            coordinator,
-            flag_auto,
-            flag_manual, // 2
-            flag_manual2, // 3
-            waiting_for_notify, // 4
-        ],
-        start_mask,
-    )
+/// Adds the tasks from `wake_bits` to the main executor, if it has enough wake
+/// bits to tell them apart, and starts the executors.
+fn start_executors(
+    tasks: &mut [core::pin::Pin<&mut dyn core::future::Future<Output = Infallible>>],
+    start_mask: exec::WakeMask,
+) -> ! {
+    #[cfg(any(feature = "wake-bits-64", target_pointer_width = "64"))]
+    wake_bits::with_far_task(tasks, start_mask, start_padded_executors);
+    #[cfg(not(any(feature = "wake-bits-64", target_pointer_width = "64")))]
+    start_padded_executors(tasks, start_mask);
 }
 
+/// Starts the interrupt executor, if we're testing it, and then the main
+/// executor.
+fn start_padded_executors(
+    tasks: &mut [core::pin::Pin<&mut dyn core::future::Future<Output = Infallible>>],
+    start_mask: exec::WakeMask,
+) -> ! {
+    #[cfg(feature = "interrupt-executor")]
+    {
+        let urgent = core::pin::pin!(interrupt::task_urgent());
+        // Safety: the urgent task only shares atomics and `Notify`s with the
+        // other tasks.
+        unsafe {
+            interrupt::EXECUTOR.start(&mut [urgent], exec::ALL_TASKS, || {
+                run_main_executor(tasks, start_mask)
+            });
+        }
+    }
+    #[cfg(not(feature = "interrupt-executor"))]
+    run_main_executor(tasks, start_mask);
+}
+
+/// Starts the main executor, when we aren't testing anything that needs the
+/// `Executor` builder. This goes through `run_tasks`, so that the wrappers
+/// around the builder get tested too.
+#[cfg(not(any(
+    feature = "stats",
+    feature = "trace",
+    feature = "big-flash",
+    feature = "task-isolation",
+)))]
+fn run_main_executor(
+    tasks: &mut [core::pin::Pin<&mut dyn core::future::Future<Output = Infallible>>],
+    start_mask: exec::WakeMask,
+) -> ! {
+    exec::run_tasks(tasks, start_mask)
+}
+
+/// Starts the main executor, adding the idle task (after the others), idle
+/// hook, statistics, and tracing if we're testing them.
+#[cfg(any(
+    feature = "stats",
+    feature = "trace",
+    feature = "big-flash",
+    feature = "task-isolation",
+))]
+fn run_main_executor(
+    tasks: &mut [core::pin::Pin<&mut dyn core::future::Future<Output = Infallible>>],
+    start_mask: exec::WakeMask,
+) -> ! {
+    let executor = exec::Executor::new(tasks).initial_mask(start_mask);
+    #[cfg(feature = "stats")]
+    let executor = {
+        static STATS: exec::TaskStatsTable<{ tasks::COUNT }> =
+            exec::TaskStatsTable::new();
+        executor.task_stats(&STATS)
+    };
+    #[cfg(feature = "trace")]
+    let executor = executor.trace_sink(&TRACE);
+    #[cfg(feature = "big-flash")]
+    let idle = core::pin::pin!(task_idle());
+    #[cfg(feature = "big-flash")]
+    let executor = executor.idle_task(idle).idle_hook_with_context(idle_hook);
+    #[cfg(feature = "task-isolation")]
+    let executor = {
+        MPU.share(
+            lilos::isolation::Region::of(&SHARED_WITH_COORDINATOR),
+            tasks::coordinator,
+        );
+        executor.task_isolation(&MPU)
+    };
+    executor.run()
+}
+
+/// Deadline reported to the idle hook the last time the executor idled, in
+/// ticks (truncated, since not every target has 64-bit atomics), or
+/// `u32::MAX` if it had none.
+#[cfg(feature = "big-flash")]
+static IDLE_DEADLINE: AtomicU32 = AtomicU32::new(0);
+/// Set if the idle hook is ever told about the wrong interrupt policy.
+#[cfg(feature = "big-flash")]
+static IDLE_WRONG_POLICY: AtomicBool = AtomicBool::new(false);
+
+/// Records what the executor tells us, and then idles as usual.
+#[cfg(feature = "big-flash")]
+fn idle_hook(cx: &exec::IdleContext) {
+    let deadline = cx.next_deadline().map_or(u32::MAX, |d| u64::from(d) as u32);
+    IDLE_DEADLINE.store(deadline, Ordering::SeqCst);
+    if !matches!(cx.interrupts(), exec::Interrupts::Masked) {
+        IDLE_WRONG_POLICY.store(true, Ordering::SeqCst);
+    }
+    cx.wait_for_interrupt();
+}
+
+lilos::task_table! {
+    /// Tasks on the main executor, in the order `run_tasks` passes them.
+    mod tasks {
+        coordinator,
+        flag_auto,
+        flag_manual,
+        flag_manual2,
+        waiting_for_notify,
+        #[cfg(feature = "task-control")]
+        controlled,
+    }
+}
+
+/// Number of tasks on the main executor. This counts the padding from
+/// `wake_bits`, where it's there, and the idle task, which comes last, if
+/// we're testing it.
+#[cfg(all(feature = "interrupt-executor", not(any(feature = "wake-bits-64", target_pointer_width = "64"))))]
+const MAIN_TASKS: usize = tasks::COUNT + cfg!(feature = "big-flash") as usize;
+#[cfg(all(feature = "interrupt-executor", any(feature = "wake-bits-64", target_pointer_width = "64")))]
+const MAIN_TASKS: usize = wake_bits::TASK_COUNT + cfg!(feature = "big-flash") as usize;
+
 static AUTO_FLAG: AtomicBool = AtomicBool::new(false);
 static MUST_START_FLAG: AtomicBool = AtomicBool::new(false);
 static MUST_NOT_START_FLAG: AtomicBool = AtomicBool::new(false);
 static NOTIFY: exec::Notify = exec::Notify::new();
 static NOTIFY_REACHED: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "big-flash")]
+static IDLE_POLLS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "big-flash")]
+static IDLE_NOTIFY: exec::Notify = exec::Notify::new();
+#[cfg(feature = "task-control")]
+static CONTROLLED_POLLS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "task-control")]
+static CONTROLLED_NOTIFY: exec::Notify = exec::Notify::new();
+#[cfg(feature = "trace")]
+static TRACE: exec::TraceRing<64> = exec::TraceRing::new();
+#[cfg(feature = "poll-budget")]
+static OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "task-isolation")]
+static MPU: lilos::isolation::SoftMpu<1> = lilos::isolation::SoftMpu::new();
+#[cfg(feature = "task-isolation")]
+static SHARED_WITH_COORDINATOR: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "task-isolation")]
+static NOT_SHARED: AtomicUsize = AtomicUsize::new(0);
 
 const A_BIT: core::time::Duration = core::time::Duration::from_millis(2);
 
 macro_rules! async_tests {
     ($($name:path,)*) => {
         $(
-            cortex_m_semihosting::hprint!(concat!(stringify!($name), "... "));
+            out!(concat!(stringify!($name), "... "));
             $name().await;
-            cortex_m_semihosting::hprintln!("OK");
+            outln!("OK");
         )*
     };
 }
@@ -78,7 +296,11 @@ async fn task_coordinator() -> Infallible {
             test_sleep_until_multi,
             test_with_deadline_actively_polled,
             test_with_deadline_blocking,
+            test_virtual_time_manual,
+            test_virtual_time_next_deadline,
             test_notify,
+            test_notify_foreign_waker,
+            test_wake_mask,
             list::test_node_basics,
             list::test_list_basics,
             list::test_insert_and_wait,
@@ -102,6 +324,105 @@ async fn task_coordinator() -> Infallible {
             handoff::test_pop_cancel_after_block,
             handoff::test_pop_cancel_after_success,
         }
+
+        #[cfg(feature = "big-flash")]
+        {
+            async_tests! {
+                branch::test_join,
+                branch::test_select_polls_woken_branch,
+                branch::test_notify_wakes_branch,
+                branch::test_notify_two_waiting_branches,
+                test_idle_task,
+                test_idle_context,
+                test_task_table,
+                test_restartable_task,
+                pool::test_spawn_and_complete,
+                pool::test_simultaneous_completion,
+                watchdog::test_missed_deadline,
+                watchdog::test_supervise_feeds,
+                test_time_units,
+                test_sleep_micros,
+            }
+        }
+
+        #[cfg(feature = "task-control")]
+        {
+            async_tests! {
+                test_task_control,
+            }
+        }
+
+        #[cfg(any(feature = "wake-bits-64", target_pointer_width = "64"))]
+        {
+            async_tests! {
+                wake_bits::test_no_aliasing,
+            }
+        }
+
+        #[cfg(feature = "interrupt-executor")]
+        {
+            async_tests! {
+                interrupt::test_notify_across_executors,
+                interrupt::test_sleep_on_interrupt_executor,
+            }
+        }
+
+        #[cfg(feature = "tickless")]
+        {
+            async_tests! {
+                test_tickless_long_sleep,
+            }
+        }
+
+        #[cfg(feature = "stats")]
+        {
+            async_tests! {
+                test_task_stats,
+            }
+        }
+
+        #[cfg(feature = "poll-budget")]
+        {
+            async_tests! {
+                test_poll_budget,
+            }
+        }
+
+        #[cfg(feature = "time-slice")]
+        {
+            async_tests! {
+                test_maybe_yield,
+            }
+        }
+
+        #[cfg(feature = "task-isolation")]
+        {
+            async_tests! {
+                test_task_isolation,
+            }
+        }
+
+        #[cfg(feature = "cortex-m-mpu")]
+        {
+            async_tests! {
+                test_cortex_m_mpu,
+            }
+        }
+
+        #[cfg(feature = "trace")]
+        {
+            async_tests! {
+                test_trace_yield,
+                test_trace_timer,
+            }
+        }
+
+        #[cfg(feature = "hosted")]
+        {
+            async_tests! {
+                hosted::test_notify_from_thread,
+            }
+        }
     };
 
     // We're going to impose a timeout, just in case something fails to wake
@@ -117,8 +438,8 @@ async fn task_coordinator() -> Infallible {
 
     match time::with_timeout(TEST_TIMEOUT, tests).await {
         Some(()) => {
-            hprintln!("tests complete.");
-            cortex_m_semihosting::debug::exit(Ok(()));
+            outln!("tests complete.");
+            exit_success();
         }
         None => {
             panic!("tests timed out.");
@@ -142,7 +463,7 @@ async fn test_other_tasks_started() {
     // Check that the manual flag did _not._
     assert!(!MUST_START_FLAG.load(Ordering::SeqCst), "flag_manual started prematurely");
     // Start the manual flag task.
-    start_task_by_index(2).await;
+    start_task_by_index(tasks::flag_manual.index()).await;
     // Manual flag should be set now.
     assert!(MUST_START_FLAG.load(Ordering::SeqCst), "flag_manual not started?");
     // Non-started manual flag should still be clear.
@@ -164,6 +485,49 @@ async fn test_sleep_until_basic() {
     assert!(t2 == target);
 }
 
+/// Sleeps for long enough that tickless idle will stretch the SysTick period
+/// over many ticks, and checks that the clock comes out exactly right.
+#[cfg(feature = "tickless")]
+async fn test_tickless_long_sleep() {
+    for ms in [2, 17, 100] {
+        let target = time::TickTime::now() + time::Millis(ms);
+        time::sleep_until(target).await;
+        assert_eq!(time::TickTime::now(), target);
+    }
+}
+
+/// Checks conversions between ticks and the interval types. These round up
+/// when computing deadlines, and down when measuring intervals.
+#[cfg(feature = "big-flash")]
+async fn test_time_units() {
+    use core::time::Duration;
+    use lilos::time::{Micros, Millis, TickTime, TICK_HZ};
+
+    let t0 = TickTime::from_ticks_since_boot(0);
+    let one_tick = TickTime::from_ticks_since_boot(1);
+    assert_eq!(t0 + Duration::from_nanos(1), one_tick);
+    assert_eq!(t0 + Micros(1), one_tick);
+    assert_eq!(one_tick.micros_since(t0), Micros(1_000_000 / TICK_HZ));
+
+    let second = t0 + Millis(1000);
+    assert_eq!(u64::from(second), TICK_HZ);
+    assert_eq!(t0 + Duration::from_secs(1), second);
+    assert_eq!(t0 + Micros(1_000_000), second);
+    assert_eq!(TickTime::from_micros_since_boot(1_000_000), second);
+    assert_eq!(second.millis_since(t0), Millis(1000));
+    assert_eq!(second.micros_since(t0), Micros(1_000_000));
+    assert_eq!(second.duration_since(t0), Duration::from_secs(1));
+}
+
+#[cfg(feature = "big-flash")]
+async fn test_sleep_micros() {
+    use lilos::time::{Micros, TickTime};
+
+    let start = TickTime::now();
+    time::sleep_for(Micros(1500)).await;
+    assert!(start.elapsed_micros() >= Micros(1500));
+}
+
 async fn test_sleep_until_multi() {
     futures::select_biased! {
         _ = time::sleep_for(A_BIT).fuse() => (),
@@ -211,8 +575,47 @@ async fn test_with_deadline_blocking() {
     assert!(last_poll < deadline);
 }
 
+// Note that the virtual time tests run under the test suite's overall
+// timeout, so they can't skip ahead very far without tripping it.
+
+/// Checks that virtual time stands still until advanced, and that the normal
+/// clock picks up from there afterwards.
+async fn test_virtual_time_manual() {
+    use lilos::time::TickTime;
+
+    time::start_virtual_time();
+    let t1 = TickTime::now();
+    exec::yield_cpu().await;
+    exec::yield_cpu().await;
+    assert_eq!(TickTime::now(), t1, "virtual time passed on its own");
+
+    time::advance_virtual_time(time::Millis(100));
+    assert_eq!(TickTime::now(), t1 + time::Millis(100));
+    time::stop_virtual_time();
+
+    time::sleep_for(A_BIT).await;
+    assert!(TickTime::now() >= t1 + time::Millis(100) + A_BIT);
+}
+
+/// Checks that virtual time can skip straight to a pending timer, waking the
+/// task waiting on it.
+async fn test_virtual_time_next_deadline() {
+    use lilos::time::TickTime;
+
+    time::start_virtual_time();
+    let deadline = TickTime::now() + time::Millis(50);
+    let (result, next) = futures::join!(
+        time::with_deadline(deadline, core::future::pending::<()>()),
+        async { time::advance_to_next_deadline() },
+    );
+    assert_eq!(result, None);
+    assert_eq!(next, Some(deadline));
+    assert_eq!(TickTime::now(), deadline);
+    time::stop_virtual_time();
+}
+
 async fn test_notify() {
-    start_task_by_index(4).await;
+    start_task_by_index(tasks::waiting_for_notify.index()).await;
     assert!(!NOTIFY_REACHED.load(Ordering::SeqCst));
     NOTIFY.notify();
     exec::yield_cpu().await;
@@ -220,6 +623,301 @@ async fn test_notify() {
     assert!(NOTIFY_REACHED.load(Ordering::SeqCst));
 }
 
+/// Checks that the idle task only gets polled when no other task is ready.
+#[cfg(feature = "big-flash")]
+async fn test_idle_task() {
+    let before = IDLE_POLLS.load(Ordering::SeqCst);
+    IDLE_NOTIFY.notify();
+    // We're still ready after yielding, so the idle task shouldn't get a turn.
+    exec::yield_cpu().await;
+    assert_eq!(IDLE_POLLS.load(Ordering::SeqCst), before);
+    // While we're asleep, nothing else is ready.
+    time::sleep_for(A_BIT).await;
+    assert_eq!(IDLE_POLLS.load(Ordering::SeqCst), before + 1);
+}
+
+async fn test_wake_mask() {
+    use exec::WakeMask;
+
+    // The mask has a bit per word-sized task index, or 64 with `wake-bits-64`,
+    // and tasks past that wrap around.
+    let bits = if cfg!(feature = "wake-bits-64") {
+        64.max(usize::BITS as usize)
+    } else {
+        usize::BITS as usize
+    };
+    let mask = WakeMask::new().with_task(0);
+    assert!(mask.contains(0));
+    assert!(!mask.contains(1));
+    assert!(!mask.contains(bits / 2));
+    assert!(mask.contains(bits));
+
+    let mask = WakeMask::new().with_task(bits / 2 + 1);
+    assert!(mask.contains(bits / 2 + 1));
+    assert!(!mask.contains(1));
+    assert!(!mask.is_empty());
+    assert!(WakeMask::new().is_empty());
+
+    // Plain usize masks keep their old meaning: each bit names every task
+    // with a matching index modulo the word size.
+    let bits = usize::BITS as usize;
+    let mask = WakeMask::from(0b10);
+    assert!(mask.contains(1));
+    assert!(mask.contains(1 + bits));
+    assert!(!mask.contains(0));
+    assert_eq!(WakeMask::from(exec::ALL_TASKS), (0..64).fold(
+        WakeMask::new(),
+        WakeMask::with_task,
+    ));
+}
+
+/// Checks the constants that `task_table!` generates.
+#[cfg(feature = "big-flash")]
+async fn test_task_table() {
+    use exec::WakeMask;
+
+    assert_eq!(tasks::coordinator.index(), 0);
+    assert_eq!(tasks::waiting_for_notify.index(), 4);
+    // `controlled` is only there with the `task-control` feature.
+    let count = if cfg!(feature = "task-control") { 6 } else { 5 };
+    assert_eq!(tasks::COUNT, count);
+    assert_eq!(tasks::ALL, (0..count).fold(WakeMask::new(), WakeMask::with_task));
+    assert_eq!(WakeMask::from(tasks::flag_auto), WakeMask::new().with_task(1));
+
+    const FLAGS: WakeMask = lilos::task_mask!(tasks::flag_manual, tasks::flag_manual2);
+    assert!(FLAGS.contains(2));
+    assert!(FLAGS.contains(3));
+    assert!(!FLAGS.contains(1));
+}
+
+#[cfg(feature = "stats")]
+async fn test_task_stats() {
+    let coordinator = tasks::coordinator.index();
+    let before = exec::task_stats(coordinator).unwrap();
+    exec::yield_cpu().await;
+    exec::yield_cpu().await;
+    let after = exec::task_stats(coordinator).unwrap();
+
+    assert_eq!(after.polls, before.polls + 2);
+    assert!(after.total_poll_time >= before.total_poll_time);
+    assert!(u64::from(after.max_poll_time) <= after.total_poll_time);
+    // Nothing shares a wake bit with the coordinator, so it's never polled
+    // without being woken.
+    assert_eq!(after.spurious_polls, 0);
+    // `flag_manual2` is never started, so it's never polled.
+    assert_eq!(exec::task_stats(tasks::flag_manual2.index()).unwrap().polls, 0);
+    // Indices past the end of the table aren't tracked.
+    assert_eq!(exec::task_stats(tasks::COUNT), None);
+}
+
+/// Makes the current poll take some time, whether the OS is measuring it in
+/// cycles or in ticks. Interrupts are masked while we're polled, so the tick
+/// can't advance on its own; instead, this moves virtual time forward a
+/// millisecond. Time only ever moves forward, so at worst, other tests' timers
+/// go off a little early in real time.
+#[cfg(any(feature = "poll-budget", feature = "time-slice"))]
+fn take_some_time() {
+    time::start_virtual_time();
+    time::advance_virtual_time(time::Millis(1));
+    time::stop_virtual_time();
+}
+
+/// Poll budget hook that counts the coordinator's overruns.
+#[cfg(feature = "poll-budget")]
+fn count_overrun(overrun: exec::PollOverrun) {
+    if overrun.task == tasks::coordinator.index() {
+        assert!(overrun.elapsed > overrun.budget);
+        OVERRUNS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Checks that polls over budget are reported, and polls within it aren't.
+#[cfg(feature = "poll-budget")]
+async fn test_poll_budget() {
+    let overruns = || OVERRUNS.load(Ordering::SeqCst);
+
+    exec::set_poll_budget(u32::MAX - 1, count_overrun);
+    exec::yield_cpu().await;
+    assert_eq!(overruns(), 0);
+
+    exec::set_poll_budget(0, count_overrun);
+    take_some_time();
+    exec::yield_cpu().await;
+    // Turn checking off before this poll ends, so it isn't reported.
+    exec::clear_poll_budget();
+    assert_eq!(overruns(), 1);
+}
+
+/// Checks that `maybe_yield` only yields once the time slice is used up.
+#[cfg(feature = "time-slice")]
+async fn test_maybe_yield() {
+    exec::set_time_slice(u32::MAX - 1);
+    assert!(
+        exec::maybe_yield().now_or_never().is_some(),
+        "yielded within the slice",
+    );
+
+    exec::set_time_slice(0);
+    take_some_time();
+    assert!(
+        exec::maybe_yield().now_or_never().is_none(),
+        "didn't yield after the slice",
+    );
+
+    exec::clear_time_slice();
+    assert!(
+        exec::maybe_yield().now_or_never().is_some(),
+        "yielded with no slice",
+    );
+}
+
+/// Checks that a task may access its own future and what's shared with it,
+/// and nothing else.
+#[cfg(feature = "task-isolation")]
+async fn test_task_isolation() {
+    use lilos::isolation::{self, AccessFault, Region};
+
+    assert_eq!(isolation::isolated_task(), Some(tasks::coordinator.index()));
+    // This is kept across an `await`, so it lives in the task's future.
+    let local = 0u32;
+    exec::yield_cpu().await;
+    assert_eq!(MPU.check_ref(&local), Ok(()));
+    assert_eq!(MPU.check_ref(&SHARED_WITH_COORDINATOR), Ok(()));
+    assert_eq!(
+        MPU.check_ref(&NOT_SHARED),
+        Err(AccessFault {
+            task: tasks::coordinator.index(),
+            access: Region::of(&NOT_SHARED),
+        }),
+    );
+}
+
+/// Checks how `CortexMMpu` programs the MPU around a poll. The memory it
+/// protects is set aside for the purpose, so that the test can keep running
+/// with the MPU on.
+#[cfg(feature = "cortex-m-mpu")]
+async fn test_cortex_m_mpu() {
+    use lilos::isolation::{CortexMMpu, Region, TaskIsolation};
+
+    /// The MPU can only describe regions that are aligned to their size.
+    #[repr(align(32))]
+    struct Aligned([u8; 32]);
+    static PROTECTED: Aligned = Aligned([0; 32]);
+    static GRANTED: Aligned = Aligned([0; 32]);
+    let storage = Aligned([0; 32]);
+
+    let task = tasks::coordinator.index();
+    let mpu = CortexMMpu::<1>::new(Region::of(&PROTECTED));
+    mpu.share(Region::of(&GRANTED), tasks::coordinator);
+
+    // Safety: nothing else in the test suite uses the MPU, and we only read
+    // back what `CortexMMpu` programmed.
+    let regs = unsafe { &*cortex_m::peripheral::MPU::PTR };
+    let read_region = |n: u32| {
+        unsafe { regs.rnr.write(n) };
+        (regs.rbar.read(), regs.rasr.read())
+    };
+    mpu.enter(task, Region::of(&storage));
+    let ctrl = regs.ctrl.read();
+    let programmed = [0, 1, 2, 3].map(read_region);
+    mpu.leave(task);
+
+    assert_eq!(ctrl & 1, 1, "MPU not enabled");
+    assert_eq!(regs.ctrl.read() & 1, 0, "MPU left on");
+    // Protected region first, then holes punched in it for the task's storage
+    // and the region shared with it. RBAR holds the base address, and RASR
+    // holds the access permissions in bits 24-26 and an enable bit in bit 0.
+    let expected = [
+        (Region::of(&PROTECTED), 0b000),
+        (Region::of(&storage), 0b011),
+        (Region::of(&GRANTED), 0b011),
+    ];
+    for ((rbar, rasr), (region, ap)) in programmed.iter().zip(expected) {
+        assert_eq!(*rbar as usize & !0x1F, region.base);
+        assert_eq!(rasr >> 24 & 0b111, ap);
+        assert_eq!(rasr & 1, 1);
+    }
+    // Nothing else is left enabled.
+    assert_eq!(programmed[3].1 & 1, 0);
+}
+
+/// Checks that the events in `TRACE` since sequence number `since` include
+/// `expected`, in order (but not necessarily adjacent).
+#[cfg(feature = "trace")]
+fn assert_traced(since: usize, expected: &[exec::TraceEvent]) {
+    let mut expected = expected.iter().peekable();
+    for r in TRACE.records().filter(|r| r.seq >= since) {
+        if expected.peek() == Some(&&r.event) {
+            expected.next();
+        }
+    }
+    assert!(expected.next().is_none());
+}
+
+#[cfg(feature = "trace")]
+async fn test_trace_yield() {
+    use exec::TraceEvent;
+
+    let since = TRACE.total();
+    exec::yield_cpu().await;
+    assert_traced(since, &[
+        TraceEvent::WakeTask(0),
+        TraceEvent::PollEnd(0),
+        TraceEvent::PollStart(0),
+    ]);
+}
+
+#[cfg(feature = "trace")]
+async fn test_trace_timer() {
+    use exec::TraceEvent;
+
+    let since = TRACE.total();
+    time::sleep_for(A_BIT).await;
+    assert_traced(since, &[
+        TraceEvent::PollEnd(0),
+        TraceEvent::TimersFired,
+        TraceEvent::WakeTask(0),
+        TraceEvent::PollStart(0),
+    ]);
+}
+
+/// Checks that a `Notify` really wakes a `Waker` that didn't come from the
+/// executor, rather than treating it as a task waker.
+async fn test_notify_foreign_waker() {
+    use core::task::{RawWaker, RawWakerVTable, Waker};
+    use lilos::atomic::AtomicArithExt;
+
+    static WAKES: AtomicUsize = AtomicUsize::new(0);
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |p| RawWaker::new(p, &VTABLE),
+        |_| { WAKES.fetch_add_polyfill(1, Ordering::SeqCst); },
+        |_| { WAKES.fetch_add_polyfill(1, Ordering::SeqCst); },
+        |_| (),
+    );
+    // Safety: the vtable functions don't look at the pointer at all.
+    let waker = unsafe {
+        Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE))
+    };
+
+    let notify = exec::Notify::new();
+    WAKES.store(0, Ordering::SeqCst);
+    notify.subscribe(&waker);
+    if cfg!(feature = "foreign-wakers") {
+        // Held until notified...
+        assert_eq!(WAKES.load(Ordering::SeqCst), 0);
+        notify.subscribe(&waker);
+        assert_eq!(WAKES.load(Ordering::SeqCst), 0);
+        notify.notify();
+        assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+        // ...and then forgotten.
+        notify.notify();
+        assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+    } else {
+        // Woken immediately.
+        assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // Utility functions and task constructors
 
@@ -234,6 +932,125 @@ async fn task_set_a_flag_then_halt(flag: &AtomicBool) -> Infallible {
     block_forever().await
 }
 
+/// Checks that the idle hook hears about the deadline we're sleeping until.
+#[cfg(feature = "big-flash")]
+async fn test_idle_context() {
+    let deadline = time::TickTime::now() + A_BIT;
+    time::sleep_until(deadline).await;
+    // Nothing else wakes up before the suite's timeout, so this was the
+    // earliest deadline while we slept.
+    assert_eq!(IDLE_DEADLINE.load(Ordering::SeqCst), u64::from(deadline) as u32);
+    assert!(!IDLE_WRONG_POLICY.load(Ordering::SeqCst));
+}
+
+/// Checks that a suspended task's wakeups are held until it's resumed, and
+/// that a stopped task's are thrown away until it's started.
+#[cfg(feature = "task-control")]
+async fn test_task_control() {
+    const TASK: usize = tasks::controlled.index();
+    let polls = || CONTROLLED_POLLS.load(Ordering::SeqCst);
+
+    // The task isn't in the initial mask, so it hasn't run yet.
+    assert_eq!(polls(), 0);
+    exec::suspend_task(TASK);
+    assert!(exec::is_task_suspended(TASK));
+    start_task_by_index(TASK).await;
+    assert_eq!(polls(), 0, "suspended task polled");
+    exec::resume_task(TASK);
+    assert!(!exec::is_task_suspended(TASK));
+    exec::yield_cpu().await;
+    exec::yield_cpu().await;
+    assert_eq!(polls(), 1, "latched wakeup not delivered on resume");
+
+    // Resuming with nothing latched doesn't wake it.
+    exec::suspend_task(TASK);
+    exec::resume_task(TASK);
+    exec::yield_cpu().await;
+    exec::yield_cpu().await;
+    assert_eq!(polls(), 1);
+
+    // Stopping throws away latched wakeups...
+    exec::suspend_task(TASK);
+    CONTROLLED_NOTIFY.notify();
+    exec::yield_cpu().await;
+    exec::yield_cpu().await;
+    exec::stop_task(TASK);
+    exec::resume_task(TASK);
+    exec::yield_cpu().await;
+    exec::yield_cpu().await;
+    assert_eq!(polls(), 1, "stopped task kept its wakeup");
+
+    // ...and starting polls it anyway.
+    exec::stop_task(TASK);
+    exec::start_task(TASK);
+    assert!(!exec::is_task_suspended(TASK));
+    exec::yield_cpu().await;
+    exec::yield_cpu().await;
+    assert_eq!(polls(), 2, "started task not polled");
+}
+
+/// Checks that restarting a `RestartableTask` drops its future and builds a
+/// new one in its place.
+#[cfg(feature = "big-flash")]
+async fn test_restartable_task() {
+    static CONTROL: exec::RestartControl = exec::RestartControl::new();
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct CountDrop;
+    impl Drop for CountDrop {
+        fn drop(&mut self) {
+            DROPS.store(DROPS.load(Ordering::SeqCst) + 1, Ordering::SeqCst);
+        }
+    }
+
+    let mut task = core::pin::pin!(exec::RestartableTask::new(&CONTROL, || {
+        BUILDS.store(BUILDS.load(Ordering::SeqCst) + 1, Ordering::SeqCst);
+        async {
+            let _guard = CountDrop;
+            block_forever().await
+        }
+    }));
+
+    // Nothing's built until the first poll.
+    assert_eq!(BUILDS.load(Ordering::SeqCst), 0);
+    assert!(futures::poll!(task.as_mut()).is_pending());
+    assert_eq!(BUILDS.load(Ordering::SeqCst), 1);
+    assert_eq!(CONTROL.restarts(), 0);
+
+    // A restart doesn't happen until the next poll.
+    CONTROL.restart();
+    assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+    assert!(futures::poll!(task.as_mut()).is_pending());
+    assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    assert_eq!(BUILDS.load(Ordering::SeqCst), 2);
+    assert_eq!(CONTROL.restarts(), 1);
+
+    // Polling again without a request leaves it alone.
+    assert!(futures::poll!(task.as_mut()).is_pending());
+    assert_eq!(BUILDS.load(Ordering::SeqCst), 2);
+}
+
+/// Counts its polls, waiting for `CONTROLLED_NOTIFY` in between.
+#[cfg(feature = "task-control")]
+async fn task_controlled() -> Infallible {
+    loop {
+        let n = CONTROLLED_POLLS.load(Ordering::SeqCst);
+        CONTROLLED_POLLS.store(n + 1, Ordering::SeqCst);
+        CONTROLLED_NOTIFY.until_next().await;
+    }
+}
+
+/// Counts its polls, waiting for `IDLE_NOTIFY` in between.
+#[cfg(feature = "big-flash")]
+async fn task_idle() -> Infallible {
+    loop {
+        let n = IDLE_POLLS.load(Ordering::SeqCst);
+        IDLE_POLLS.store(n + 1, Ordering::SeqCst);
+        IDLE_NOTIFY.until_next().await;
+    }
+}
+
 async fn block_forever() -> Infallible {
     let notify = exec::Notify::new();
     loop {
@@ -0,0 +1,60 @@
+use core::cell::Cell;
+use core::future::Future;
+use core::pin::pin;
+
+use lilos::pool::TaskPool;
+use lilos::time::{self, Millis};
+
+/// Sleeps for `ms` milliseconds and then returns `value`, counting its polls
+/// in `polls`.
+async fn sleeper(polls: &Cell<usize>, ms: u64, value: usize) -> usize {
+    let mut sleep = pin!(time::sleep_for(Millis(ms)));
+    futures::future::poll_fn(|cx| {
+        polls.set(polls.get() + 1);
+        sleep.as_mut().poll(cx)
+    }).await;
+    value
+}
+
+/// Checks that futures can be spawned into a pool until it's full, that the
+/// first to finish is reported, and that the others aren't polled when they
+/// weren't woken.
+pub async fn test_spawn_and_complete() {
+    let slow_polls = Cell::new(0);
+    let fast_polls = Cell::new(0);
+    let mut pool = pin!(TaskPool::<_, 2>::new());
+    assert!(pool.is_empty());
+
+    assert_eq!(pool.as_mut().spawn(sleeper(&slow_polls, 100, 10)).ok(), Some(0));
+    assert_eq!(pool.as_mut().spawn(sleeper(&fast_polls, 2, 20)).ok(), Some(1));
+    assert!(pool.is_full());
+    assert_eq!(pool.len(), 2);
+    assert!(pool.as_mut().spawn(sleeper(&fast_polls, 2, 30)).is_err());
+
+    assert_eq!(pool.as_mut().next_completion().await, (1, 20));
+    // The slow one was only polled to start its timer.
+    assert_eq!(slow_polls.get(), 1);
+    assert_eq!(fast_polls.get(), 2);
+
+    // The finished future's slot is free again.
+    assert_eq!(pool.len(), 1);
+    assert_eq!(pool.as_mut().spawn(sleeper(&fast_polls, 2, 30)).ok(), Some(1));
+    assert_eq!(pool.as_mut().next_completion().await, (1, 30));
+
+    assert!(pool.as_mut().cancel(0));
+    assert!(!pool.as_mut().cancel(0));
+    assert!(pool.is_empty());
+}
+
+/// Checks that when several futures finish at once, each is reported in turn.
+pub async fn test_simultaneous_completion() {
+    let polls = Cell::new(0);
+    let mut pool = pin!(TaskPool::<_, 3>::new());
+    for value in 0..3 {
+        pool.as_mut().spawn(sleeper(&polls, 2, value)).ok().unwrap();
+    }
+    for value in 0..3 {
+        assert_eq!(pool.as_mut().next_completion().await, (value, value));
+    }
+    assert!(pool.is_empty());
+}
@@ -0,0 +1,139 @@
+//! Tests for `Scheduling::Priority`.
+//!
+//! Under priority scheduling, the rest of the suite's coordinator (task 0)
+//! would starve every other task whenever it yielded, so these tests get an
+//! executor of their own. The hosted wrapper runs them in a child process.
+//!
+//! Most of the tasks here just log their index each time they're polled. The
+//! bitmap in hosted builds is a single 64-bit word, so `SHARED_ALIAS` shares a
+//! wake bit (and thus a priority) with `SHARED`. Some tests need that, and
+//! some need bits that don't name any task, so the executor is run with
+//! either all of the tasks, or only the first `FEW_TASKS`.
+
+use core::convert::Infallible;
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use std::sync::Mutex;
+
+use lilos::exec::{self, Executor, Scheduling, WakeMask};
+use lilos::time;
+
+const URGENT: usize = 0;
+const WAKER: usize = 1;
+const BACKGROUND: usize = 2;
+const SHARED: usize = 3;
+const LATER: usize = 4;
+/// The task running the tests comes after the ones it's testing, and has no
+/// wake bit in common with any other task.
+const DRIVER: usize = 5;
+const FEW_TASKS: usize = DRIVER + 1;
+const SHARED_ALIAS: usize = SHARED + usize::BITS as usize;
+const TASK_COUNT: usize = SHARED_ALIAS + 1;
+
+/// Indices of the logging tasks, in the order they were polled.
+static LOG: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+/// Number of times the executor has idled.
+static IDLES: AtomicUsize = AtomicUsize::new(0);
+
+/// Logs its index when polled, and never finishes. The one at `WAKER` also
+/// wakes `URGENT` each time.
+struct Logged(usize);
+
+impl Future for Logged {
+    type Output = Infallible;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Infallible> {
+        LOG.lock().unwrap().push(self.0);
+        if self.0 == WAKER {
+            exec::wake_task_by_index(URGENT);
+        }
+        Poll::Pending
+    }
+}
+
+/// Takes the log, leaving it empty.
+fn take_log() -> Vec<usize> {
+    core::mem::take(&mut *LOG.lock().unwrap())
+}
+
+/// Runs the tests on a priority-scheduled executor, and exits when they pass.
+/// If `aliased`, this runs the tests that need tasks to share wake bits;
+/// otherwise, it runs the rest, with too few tasks to fill the bitmap.
+pub fn run(aliased: bool) -> ! {
+    let driver = pin!(task_driver(aliased));
+    let mut driver = Some(driver as Pin<&mut dyn Future<Output = Infallible>>);
+    let mut logged: [Logged; TASK_COUNT] = core::array::from_fn(Logged);
+    let mut all = logged.iter_mut().map(|l| match l.0 {
+        DRIVER => driver.take().unwrap(),
+        _ => Pin::new(l) as Pin<&mut dyn Future<Output = Infallible>>,
+    });
+    let mut tasks: [_; TASK_COUNT] = core::array::from_fn(|_| all.next().unwrap());
+    let count = if aliased { TASK_COUNT } else { FEW_TASKS };
+
+    Executor::new(&mut tasks[..count])
+        .initial_mask(WakeMask::new().with_task(DRIVER))
+        .scheduling(Scheduling::Priority)
+        .idle_hook_with_context(|cx| {
+            IDLES.fetch_add(1, Ordering::SeqCst);
+            cx.wait_for_interrupt();
+        })
+        .run()
+}
+
+macro_rules! priority_tests {
+    ($($name:ident,)*) => {
+        $(
+            crate::out!(concat!("priority::", stringify!($name), "... "));
+            $name().await;
+            crate::outln!("OK");
+        )*
+    };
+}
+
+async fn task_driver(aliased: bool) -> Infallible {
+    if aliased {
+        priority_tests! {
+            test_shared_wake_bit,
+        }
+    } else {
+        priority_tests! {
+            test_woken_mid_pass,
+            test_idle_past_last_task,
+        }
+    }
+    crate::exit_success();
+    unreachable!()
+}
+
+/// Checks that a task woken by a lower-priority task is polled before the
+/// other tasks that were already waiting, instead of after them.
+async fn test_woken_mid_pass() {
+    take_log();
+    exec::wake_tasks_by_mask(WakeMask::new().with_task(WAKER).with_task(BACKGROUND));
+    time::sleep_for(crate::A_BIT).await;
+    assert_eq!(take_log(), [WAKER, URGENT, BACKGROUND]);
+}
+
+/// Checks that waking one of two tasks that share a wake bit polls both, at
+/// the priority of the lower-numbered one.
+async fn test_shared_wake_bit() {
+    take_log();
+    exec::wake_tasks_by_mask(WakeMask::new().with_task(SHARED_ALIAS).with_task(LATER));
+    time::sleep_for(crate::A_BIT).await;
+    assert_eq!(take_log(), [SHARED, SHARED_ALIAS, LATER]);
+}
+
+/// Checks that waking `ALL_TASKS`, most of whose bits name tasks that don't
+/// exist here, doesn't stop the executor from idling once the real tasks have
+/// been polled.
+async fn test_idle_past_last_task() {
+    exec::wake_tasks_by_mask(exec::ALL_TASKS);
+    let idles = IDLES.load(Ordering::SeqCst);
+    time::sleep_for(crate::A_BIT).await;
+    assert!(IDLES.load(Ordering::SeqCst) > idles);
+    // Every real task got polled in priority order, and `URGENT` again when
+    // `WAKER` woke it.
+    assert_eq!(take_log(), [URGENT, WAKER, URGENT, BACKGROUND, SHARED, LATER]);
+}
@@ -0,0 +1,63 @@
+//! Tests for wake bitmaps with room for more than 32 tasks.
+//!
+//! These pad the main executor out with tasks that never finish, so that the
+//! last one, the "far" task, has index 32. With only 32 wake bits it would
+//! share a bit with the coordinator (task 0); with more, it mustn't.
+
+use core::convert::Infallible;
+use core::future::{pending, Future, Pending};
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use lilos::exec;
+use lilos::time;
+
+/// Index of the far task.
+pub const FAR_TASK: usize = 32;
+/// Number of tasks on the main executor, not counting the idle task.
+pub const TASK_COUNT: usize = FAR_TASK + 1;
+
+/// Number of times the far task has been polled.
+static FAR_POLLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts its polls, and otherwise does nothing.
+async fn task_far() -> Infallible {
+    futures::future::poll_fn(|_| {
+        FAR_POLLS.fetch_add(1, Ordering::SeqCst);
+        core::task::Poll::Pending
+    }).await
+}
+
+/// Pads `tasks` out to `TASK_COUNT` with the far task at the end, and hands
+/// the result, and `start_mask`, to `run`.
+pub fn with_far_task(
+    tasks: &mut [Pin<&mut dyn Future<Output = Infallible>>],
+    start_mask: exec::WakeMask,
+    run: fn(&mut [Pin<&mut dyn Future<Output = Infallible>>], exec::WakeMask) -> !,
+) -> ! {
+    let mut filler: [Pending<Infallible>; FAR_TASK - crate::tasks::COUNT] =
+        [(); FAR_TASK - crate::tasks::COUNT].map(|_| pending());
+    let far = pin!(task_far());
+    type Task<'a> = Pin<&'a mut dyn Future<Output = Infallible>>;
+    let mut all = tasks.iter_mut()
+        .map(|t| t.as_mut() as Task<'_>)
+        .chain(filler.iter_mut().map(|f| Pin::new(f) as Task<'_>))
+        .chain(core::iter::once(far as Task<'_>));
+    let mut padded: [_; TASK_COUNT] = core::array::from_fn(|_| all.next().unwrap());
+    run(&mut padded, start_mask)
+}
+
+/// Checks that waking the coordinator doesn't poll the far task, and that
+/// waking the far task does.
+pub async fn test_no_aliasing() {
+    let before = FAR_POLLS.load(Ordering::SeqCst);
+    // Each yield wakes the coordinator, and only the coordinator.
+    exec::yield_cpu().await;
+    exec::yield_cpu().await;
+    assert_eq!(FAR_POLLS.load(Ordering::SeqCst), before);
+
+    exec::wake_task_by_index(FAR_TASK);
+    // The executor polls everything that's ready before it idles.
+    time::sleep_for(crate::A_BIT).await;
+    assert_eq!(FAR_POLLS.load(Ordering::SeqCst), before + 1);
+}
@@ -0,0 +1,70 @@
+use core::cell::Cell;
+
+use lilos::time::{self, Millis};
+use lilos::watchdog::Watchdog;
+
+/// Checks that only registered tasks that have missed their deadline are
+/// reported, and that checking in makes them healthy again.
+pub async fn test_missed_deadline() {
+    static WATCHDOG: Watchdog<3> = Watchdog::new();
+    let missed = Cell::new(0usize);
+    let note_missed = |task: usize| missed.set(missed.get() | 1 << task);
+
+    WATCHDOG.register(0, Millis(2));
+    WATCHDOG.register(1, Millis(1000));
+    // Task 2 never registers, so it's never overdue.
+    assert!(WATCHDOG.check(note_missed));
+    assert_eq!(missed.get(), 0);
+
+    time::sleep_for(Millis(5)).await;
+    WATCHDOG.check_in(1);
+    assert!(!WATCHDOG.check(note_missed));
+    assert_eq!(missed.get(), 0b001);
+
+    // Still overdue until it checks in.
+    missed.set(0);
+    assert!(!WATCHDOG.check(note_missed));
+    assert_eq!(missed.get(), 0b001);
+
+    missed.set(0);
+    WATCHDOG.check_in(0);
+    assert!(WATCHDOG.check(note_missed));
+    WATCHDOG.unregister(0);
+    time::sleep_for(Millis(5)).await;
+    assert!(WATCHDOG.check(note_missed));
+    assert_eq!(missed.get(), 0);
+}
+
+/// Checks that the supervisor feeds the hardware watchdog only while every
+/// task is on time.
+pub async fn test_supervise_feeds() {
+    static WATCHDOG: Watchdog<1> = Watchdog::new();
+    let missed = Cell::new(0);
+    let feeds = Cell::new(0);
+
+    WATCHDOG.register(0, Millis(1000));
+    time::with_timeout(
+        Millis(10),
+        WATCHDOG.supervise(
+            Millis(1),
+            |_| missed.set(missed.get() + 1),
+            || feeds.set(feeds.get() + 1),
+        ),
+    ).await;
+    assert_eq!(missed.get(), 0);
+    assert!(feeds.get() > 0);
+
+    WATCHDOG.register(0, Millis(0));
+    time::sleep_for(Millis(2)).await;
+    feeds.set(0);
+    time::with_timeout(
+        Millis(10),
+        WATCHDOG.supervise(
+            Millis(1),
+            |_| missed.set(missed.get() + 1),
+            || feeds.set(feeds.get() + 1),
+        ),
+    ).await;
+    assert!(missed.get() > 0);
+    assert_eq!(feeds.get(), 0);
+}